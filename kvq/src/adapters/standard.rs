@@ -54,6 +54,17 @@ impl<S: KVQBinaryStoreReader, K: KVQSerializable, V: KVQSerializable> KVQStoreAd
         Ok(values?)
     }
 
+    fn get_many_exact_if_exists(s: &S, keys: &[K]) -> anyhow::Result<Vec<Option<V>>> {
+        let keys_bytes = keys
+            .iter()
+            .map(|k| k.to_bytes())
+            .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+        s.get_many_exact_if_exists(&keys_bytes)?
+            .iter()
+            .map(|r| r.as_ref().map(|v| V::from_bytes(v)).transpose())
+            .collect()
+    }
+
     fn get_leq(s: &S, key: &K, fuzzy_bytes: usize) -> anyhow::Result<Option<V>> {
         let r = s.get_leq(&key.to_bytes()?, fuzzy_bytes)?;
         match r {
@@ -1,7 +1,7 @@
 use std::{collections::BTreeMap, sync::Arc};
 use std::ops::Bound::Included;
 
-use crate::traits::{KVQBinaryStore, KVQBinaryStoreImmutable, KVQBinaryStoreReader, KVQBinaryStoreWriter, KVQPair};
+use crate::traits::{get_fuzzy_base_key, get_inclusive_end_key, get_range_scan_bounds, KVQBinaryStore, KVQBinaryStoreImmutable, KVQBinaryStoreReader, KVQBinaryStoreWriter, KVQPair, KVQWriteBatch};
 pub trait KVQBinaryStoreCachedTrait: KVQBinaryStore {
     fn flush_changes(&mut self) -> anyhow::Result<(Vec<KVQPair<Vec<u8>, Vec<u8>>>, Vec<Vec<u8>>)>;
     fn flush_simple(&mut self) -> anyhow::Result<()>;
@@ -17,6 +17,8 @@ pub enum CacheValueType {
 pub struct KVQBinaryStoreCached<S: KVQBinaryStoreReader> {
     pub store: Arc<S>,
     pub map: BTreeMap<Vec<u8>, CacheValueType>,
+    /// Read-only changes layered between `map` and `store`, shared with other caches. They are never written.
+    pub base: Option<Arc<BTreeMap<Vec<u8>, CacheValueType>>>,
     pub proper_delete_return: bool,
 }
 
@@ -25,13 +27,43 @@ impl<S: KVQBinaryStoreReader> KVQBinaryStoreCached<S> {
         Self {
             store,
             map: BTreeMap::new(),
+            base: None,
             proper_delete_return: false,
         }
     }
+    /// Returns a cache whose reads see the changes of `base` (e.g. the pending writes of another cache) over `store`,
+    /// and whose own writes are kept apart from them.
+    pub fn new_with_base(store: Arc<S>, base: Arc<BTreeMap<Vec<u8>, CacheValueType>>) -> Self {
+        Self {
+            store,
+            map: BTreeMap::new(),
+            base: Some(base),
+            proper_delete_return: false,
+        }
+    }
+    fn get_cached(&self, key: &Vec<u8>) -> Option<&CacheValueType> {
+        self.map.get(key).or_else(|| self.base.as_ref()?.get(key))
+    }
+    fn has_cached_in_range(&self, start: &Vec<u8>, end: &Vec<u8>) -> bool {
+        let range = (Included(start), Included(end));
+        self.map.range::<Vec<u8>, _>(range).next().is_some()
+            || self.base.as_ref().map_or(false, |base| base.range::<Vec<u8>, _>(range).next().is_some())
+    }
+    /// Returns the cached changes as a batch that sets the written keys and deletes the removed ones.
+    pub fn to_write_batch(&self) -> KVQWriteBatch {
+        let mut batch = KVQWriteBatch::with_capacity(self.map.len());
+        for (key, vt) in self.map.iter() {
+            match vt {
+                CacheValueType::Bytes(value) => batch.set(key.to_owned(), value.to_owned()),
+                CacheValueType::Removed => batch.delete(key.to_owned()),
+            }
+        }
+        batch
+    }
 }
 impl<S: KVQBinaryStoreImmutable> KVQBinaryStoreCachedTrait for KVQBinaryStoreCached<S> {
     fn is_removed(&self, key: &Vec<u8>) -> bool {
-        match self.map.get(key) {
+        match self.get_cached(key) {
             Some(v) => match v {
                 CacheValueType::Bytes(_) => false,
                 CacheValueType::Removed => true,
@@ -86,24 +118,7 @@ impl<S: KVQBinaryStoreImmutable> KVQBinaryStoreCachedTrait for KVQBinaryStoreCac
         Ok((set_keys, removed_keys))
     }
     fn flush_simple(&mut self) -> anyhow::Result<()> {
-        let keys_to_set: Vec<KVQPair<&Vec<u8>, &Vec<u8>>> = self.map.iter().filter(|(_, vt)|{
-            match vt {
-                CacheValueType::Bytes(_) => true,
-                CacheValueType::Removed => false,
-            }
-        }).map(|(k, vt)|{
-            match vt {
-                CacheValueType::Bytes(b) => Ok(KVQPair{
-                    key: k,
-                    value: b,
-                }),
-                CacheValueType::Removed => Err(anyhow::anyhow!("Cannot flush changes with removed keys")),
-            }
-        }).collect::<anyhow::Result<Vec<_>>>()?;
-        self.store.imm_set_many_ref(&keys_to_set)?;
-        let removed_keys = self.get_removed_keys();
-
-        self.store.imm_delete_many(&removed_keys)?;
+        self.store.imm_write_batch(self.to_write_batch())?;
         self.map.clear();
         Ok(())
     }
@@ -111,7 +126,7 @@ impl<S: KVQBinaryStoreImmutable> KVQBinaryStoreCachedTrait for KVQBinaryStoreCac
 
 impl<S: KVQBinaryStoreReader> KVQBinaryStoreReader for KVQBinaryStoreCached<S> {
     fn get_exact(&self, key: &Vec<u8>) -> anyhow::Result<Vec<u8>> {
-        match self.map.get(key) {
+        match self.get_cached(key) {
             Some(v) => match v {
                 CacheValueType::Bytes(b) => Ok(b.to_owned()),
                 CacheValueType::Removed => anyhow::bail!("Key {} not found", hex::encode(&key)),
@@ -120,75 +135,31 @@ impl<S: KVQBinaryStoreReader> KVQBinaryStoreReader for KVQBinaryStoreCached<S> {
         }
     }
 
-    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
-        let mut result = Vec::new();
-        for key in keys {
-            let r = self.get_exact(key)?;
-            result.push(r);
-        }
-        Ok(result)
-    }
-
-    fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
-        let key_end = key.to_vec();
-        let mut base_key = key.to_vec();
-        let key_len = base_key.len();
-        if fuzzy_bytes > key_len {
-            return Err(anyhow::anyhow!(
-                "Fuzzy bytes must be less than or equal to key length"
-            ));
-        }
-
-        let mut sum_end = 0u32;
-        for i in 0..fuzzy_bytes {
-            sum_end += key_end[key_len - i - 1] as u32;
-            base_key[key_len - i - 1] = 0;
-        }
-
-        if sum_end == 0 {
-            match self.map.get(key) {
-                Some(v) => match v {
-                    CacheValueType::Bytes(b) => Ok(Some(b.to_owned())),
-                    CacheValueType::Removed => Ok(None),
-                },
-                None => self.store.get_leq(key, fuzzy_bytes),
-            }
-        } else {
-            let rq = self
-                .map
-                .range((Included(base_key), Included(key_end)))
-                .enumerate();
-            let mut real_op_key: Option<KVQPair<Vec<u8>, Vec<u8>>> = None;
-            for (_, (k, v)) in rq {
-                let r = match v {
-                    CacheValueType::Bytes(b) => Some(KVQPair {
-                        key: k.to_owned(),
-                        value: b.to_owned(),
-                    }),
-                    CacheValueType::Removed => None,
-                };
-                if r.is_some() {
-                    real_op_key = r;
-                    break;
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let mut results = Vec::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            match self.get_cached(key) {
+                Some(CacheValueType::Bytes(b)) => results.push(Some(b.to_owned())),
+                Some(CacheValueType::Removed) => results.push(None),
+                None => {
+                    results.push(None);
+                    misses.push(i);
                 }
             }
-            if real_op_key.is_some() {
-                let rok = real_op_key.unwrap();
-                let d1_leq = self.store.get_leq_kv(key, fuzzy_bytes)?;
-                if d1_leq.is_some() {
-                    let rnt = d1_leq.unwrap();
-                    Ok(Some(if rnt.key > rok.key {
-                        rnt.value
-                    } else {
-                        rok.value
-                    }))
-                } else {
-                    Ok(Some(rok.value))
-                }
-            } else {
-                self.store.get_leq(key, fuzzy_bytes)
+        }
+        if !misses.is_empty() {
+            // the keys the cache knows nothing about are read from the store in a single batch
+            let miss_keys = misses.iter().map(|i| keys[*i].clone()).collect::<Vec<_>>();
+            for (i, value) in misses.into_iter().zip(self.store.get_many_exact_if_exists(&miss_keys)?) {
+                results[i] = value;
             }
         }
+        Ok(results)
+    }
+
+    fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.get_leq_kv(key, fuzzy_bytes)?.map(|kv| kv.value))
     }
 
     fn get_leq_kv(
@@ -196,64 +167,14 @@ impl<S: KVQBinaryStoreReader> KVQBinaryStoreReader for KVQBinaryStoreCached<S> {
         key: &Vec<u8>,
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
-        let key_end = key.to_vec();
-        let mut base_key = key.to_vec();
-        let key_len = base_key.len();
-        if fuzzy_bytes > key_len {
-            return Err(anyhow::anyhow!(
-                "Fuzzy bytes must be less than or equal to key length"
-            ));
-        }
-
-        let mut sum_end = 0u32;
-        for i in 0..fuzzy_bytes {
-            sum_end += key_end[key_len - i - 1] as u32;
-            base_key[key_len - i - 1] = 0;
-        }
-
-        if sum_end == 0 {
-            match self.map.get(key) {
-                Some(v) => match v {
-                    CacheValueType::Bytes(b) => Ok(Some(KVQPair {
-                        key: key.clone(),
-                        value: b.to_owned(),
-                    })),
-                    CacheValueType::Removed => Ok(None),
-                },
-                None => self.store.get_leq_kv(key, fuzzy_bytes),
-            }
-        } else {
-            let rq = self
-                .map
-                .range((Included(base_key), Included(key_end)))
-                .enumerate();
-            let mut real_op_key: Option<KVQPair<Vec<u8>, Vec<u8>>> = None;
-            for (_, (k, v)) in rq {
-                let r = match v {
-                    CacheValueType::Bytes(b) => Some(KVQPair {
-                        key: k.to_owned(),
-                        value: b.to_owned(),
-                    }),
-                    CacheValueType::Removed => None,
-                };
-                if r.is_some() {
-                    real_op_key = r;
-                    break;
-                }
-            }
-            if real_op_key.is_some() {
-                let rok = real_op_key.unwrap();
-                let d1_leq = self.store.get_leq_kv(key, fuzzy_bytes)?;
-                if d1_leq.is_some() {
-                    let rnt = d1_leq.unwrap();
-                    Ok(Some(if rnt.key > rok.key { rnt } else { rok }))
-                } else {
-                    Ok(Some(rok))
-                }
-            } else {
-                self.store.get_leq_kv(key, fuzzy_bytes)
-            }
+        let base_key = get_fuzzy_base_key(key, fuzzy_bytes)?;
+        if !self.has_cached_in_range(&base_key, key) {
+            return self.store.get_leq_kv(key, fuzzy_bytes);
         }
+        // the newest key of the fuzzy range that is not removed, wherever it was written
+        Ok(self
+            .get_range_kv_merged(&base_key, Some(&get_inclusive_end_key(key)), None, 1, true)?
+            .pop())
     }
 
     fn get_many_leq(
@@ -283,7 +204,7 @@ impl<S: KVQBinaryStoreReader> KVQBinaryStoreReader for KVQBinaryStoreCached<S> {
     }
 
     fn get_exact_if_exists(&self, key: &Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
-        match self.map.get(key) {
+        match self.get_cached(key) {
             Some(v) => match v {
                 CacheValueType::Bytes(b) => Ok(Some(b.to_owned())),
                 CacheValueType::Removed => Ok(None),
@@ -296,40 +217,84 @@ impl<S: KVQBinaryStoreReader> KVQBinaryStoreReader for KVQBinaryStoreCached<S> {
         key: &Vec<u8>,
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
-        let key_end = key.to_vec();
-        let mut base_key = key.to_vec();
-        let key_len = base_key.len();
-        if fuzzy_bytes > key_len {
-            return Err(anyhow::anyhow!(
-                "Fuzzy bytes must be less than or equal to key length"
-            ));
+        let base_key = get_fuzzy_base_key(key, fuzzy_bytes)?;
+        if !self.has_cached_in_range(&base_key, key) {
+            return self.store.get_fuzzy_range_leq_kv(key, fuzzy_bytes);
         }
+        self.get_range_kv_merged(&base_key, Some(&get_inclusive_end_key(key)), None, usize::MAX, false)
+    }
+
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.get_range_kv_merged(start, end, start_after, limit, false)
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.get_range_kv_merged(start, end, start_after, limit, true)
+    }
+}
+
+impl<S: KVQBinaryStoreReader> KVQBinaryStoreCached<S> {
+    fn get_range_kv_merged(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+        reverse: bool,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        let bounds = match get_range_scan_bounds(start, end, start_after, reverse) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
+        let mut pending = match &self.base {
+            Some(base) => base.range(bounds.clone()).collect::<BTreeMap<_, _>>(),
+            None => BTreeMap::new(),
+        };
+        pending.extend(self.map.range(bounds));
+
+        // every pending write can shadow at most one key of the backing store, so fetching
+        // `limit + pending.len()` keys from the store is enough to fill the page
+        let store_limit = limit.saturating_add(pending.len());
+        let stored = if reverse {
+            self.store
+                .get_range_kv_reverse(start, end, start_after, store_limit)?
+        } else {
+            self.store.get_range_kv(start, end, start_after, store_limit)?
+        };
 
-        for i in 0..fuzzy_bytes {
-            base_key[key_len - i - 1] = 0;
+        let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = stored
+            .into_iter()
+            .map(|kv| (kv.key, kv.value))
+            .collect();
+        for (k, v) in pending {
+            match v {
+                CacheValueType::Bytes(b) => {
+                    merged.insert(k.to_owned(), b.to_owned());
+                }
+                CacheValueType::Removed => {
+                    merged.remove(k);
+                }
+            }
         }
 
-        Ok(self
-            .map
-            .range((Included(base_key), Included(key_end)))
-            .filter(|x| match x.1 {
-                CacheValueType::Bytes(_) => true,
-                CacheValueType::Removed => false,
-            })
-            .map(|(k, v)| KVQPair {
-                key: k.to_owned(),
-                value: match v {
-                    CacheValueType::Bytes(b) => b.to_owned(),
-                    CacheValueType::Removed => Vec::new(),
-                },
-            })
-            .chain(
-                self.store
-                    .get_fuzzy_range_leq_kv(key, fuzzy_bytes)?
-                    .into_iter()
-                    .filter(|x| !self.map.contains_key(&x.key)),
-            )
-            .collect::<Vec<_>>())
+        let to_pair = |(key, value): (Vec<u8>, Vec<u8>)| KVQPair { key, value };
+        if reverse {
+            Ok(merged.into_iter().rev().take(limit).map(to_pair).collect())
+        } else {
+            Ok(merged.into_iter().take(limit).map(to_pair).collect())
+        }
     }
 }
 
@@ -403,4 +368,99 @@ impl<S: KVQBinaryStoreReader> KVQBinaryStoreWriter for KVQBinaryStoreCached<S> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{CacheValueType, KVQBinaryStoreCached};
+    use crate::memory::simple::KVQSimpleMemoryBackingStore;
+    use crate::traits::{KVQBinaryStoreReader, KVQBinaryStoreWriter};
+
+    // a fuzzy key: a 4 byte prefix and a big endian version in the last 8 bytes
+    fn versioned_key(version: u64) -> Vec<u8> {
+        let mut key = vec![0, 0, 1, 0];
+        key.extend_from_slice(&version.to_be_bytes());
+        key
+    }
+
+    fn cached_store(saved: &[u64]) -> KVQBinaryStoreCached<KVQSimpleMemoryBackingStore> {
+        let mut store = KVQSimpleMemoryBackingStore::new();
+        for version in saved {
+            store.set(versioned_key(*version), vec![*version as u8]).unwrap();
+        }
+        KVQBinaryStoreCached::new(Arc::new(store))
+    }
+
+    #[test]
+    fn get_leq_returns_the_newest_cached_version() {
+        let mut cached = cached_store(&[1]);
+        cached.set(versioned_key(3), vec![3]).unwrap();
+        cached.set(versioned_key(5), vec![5]).unwrap();
+
+        assert_eq!(cached.get_leq(&versioned_key(9), 8).unwrap(), Some(vec![5]));
+        assert_eq!(cached.get_leq_kv(&versioned_key(4), 8).unwrap().map(|kv| kv.key), Some(versioned_key(3)));
+        assert_eq!(cached.get_leq(&versioned_key(2), 8).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn get_leq_prefers_newer_saved_versions() {
+        let mut cached = cached_store(&[7]);
+        cached.set(versioned_key(3), vec![3]).unwrap();
+
+        assert_eq!(cached.get_leq(&versioned_key(9), 8).unwrap(), Some(vec![7]));
+        assert_eq!(cached.get_leq(&versioned_key(6), 8).unwrap(), Some(vec![3]));
+    }
+
+    #[test]
+    fn get_leq_skips_removed_versions() {
+        let mut cached = cached_store(&[1, 2]);
+        cached.delete(&versioned_key(2)).unwrap();
+
+        assert_eq!(cached.get_leq(&versioned_key(9), 8).unwrap(), Some(vec![1]));
+        assert_eq!(cached.get_leq_kv(&versioned_key(9), 8).unwrap().map(|kv| kv.key), Some(versioned_key(1)));
+        cached.delete(&versioned_key(1)).unwrap();
+        assert_eq!(cached.get_leq(&versioned_key(9), 8).unwrap(), None);
+    }
+
+    #[test]
+    fn get_fuzzy_range_leq_kv_is_sorted_and_skips_removed_versions() {
+        let mut cached = cached_store(&[1, 4, 5]);
+        cached.set(versioned_key(3), vec![3]).unwrap();
+        cached.delete(&versioned_key(4)).unwrap();
 
+        let keys = cached
+            .get_fuzzy_range_leq_kv(&versioned_key(9), 8)
+            .unwrap()
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![versioned_key(1), versioned_key(3), versioned_key(5)]);
+    }
+
+    #[test]
+    fn reads_see_the_base_and_writes_stay_apart() {
+        let mut parent = cached_store(&[1, 2]);
+        parent.set(versioned_key(3), vec![3]).unwrap();
+        parent.delete(&versioned_key(2)).unwrap();
+        let base = Arc::new(std::mem::take(&mut parent.map));
+
+        let mut cached = KVQBinaryStoreCached::new_with_base(Arc::clone(&parent.store), Arc::clone(&base));
+        assert_eq!(cached.get_exact_if_exists(&versioned_key(3)).unwrap(), Some(vec![3]));
+        assert_eq!(cached.get_exact_if_exists(&versioned_key(2)).unwrap(), None);
+        assert_eq!(cached.get_leq(&versioned_key(9), 8).unwrap(), Some(vec![3]));
+
+        cached.set(versioned_key(4), vec![4]).unwrap();
+        cached.delete(&versioned_key(3)).unwrap();
+        assert_eq!(cached.get_leq(&versioned_key(9), 8).unwrap(), Some(vec![4]));
+        let keys = cached
+            .get_fuzzy_range_leq_kv(&versioned_key(9), 8)
+            .unwrap()
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![versioned_key(1), versioned_key(4)]);
+
+        assert_eq!(cached.map.len(), 2);
+        assert_eq!(base.get(&versioned_key(3)), Some(&CacheValueType::Bytes(vec![3])));
+    }
+}
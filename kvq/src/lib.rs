@@ -3,4 +3,6 @@ pub mod base_types;
 pub mod memory;
 pub mod traits;
 pub mod cache;
-pub mod db_traits;
\ No newline at end of file
+pub mod db_traits;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
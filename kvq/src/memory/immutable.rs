@@ -1,6 +1,6 @@
 use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::traits::{KVQBinaryStore, KVQBinaryStoreImmutable, KVQBinaryStoreReader, KVQBinaryStoreWriter, KVQBinaryStoreWriterImmutable, KVQPair};
+use crate::traits::{KVQBinaryStore, KVQBinaryStoreImmutable, KVQBinaryStoreReader, KVQBinaryStoreWriter, KVQBinaryStoreWriterImmutable, KVQPair, KVQWriteBatch};
 
 
 pub struct KVQImmutableStoreWrapper<KVQ: KVQBinaryStore> {
@@ -13,14 +13,16 @@ impl<KVQ: KVQBinaryStore> KVQImmutableStoreWrapper<KVQ> {
         }
     }
 
+    /// Blocks until no other thread is reading or writing.
     pub fn write(&self) -> anyhow::Result<RwLockWriteGuard<KVQ>> {
         self.inner
-            .try_write()
+            .write()
             .map_err(|err| anyhow::anyhow!("Error writing to immutable store: {:?}", err))
     }
+    /// Blocks while another thread is writing.
     pub fn read(&self) -> anyhow::Result<RwLockReadGuard<KVQ>> {
         self.inner
-            .try_read()
+            .read()
             .map_err(|err| anyhow::anyhow!("Error reading from immutable store: {:?}", err))
     }
 }
@@ -38,9 +40,9 @@ impl<KVQ: KVQBinaryStore> KVQBinaryStoreReader for KVQImmutableStoreWrapper<KVQ>
         }
     }
 
-    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
         {
-            self.read()?.get_many_exact(keys)
+            self.read()?.get_many_exact_if_exists(keys)
         }
     }
 
@@ -89,6 +91,30 @@ impl<KVQ: KVQBinaryStore> KVQBinaryStoreReader for KVQImmutableStoreWrapper<KVQ>
             self.read()?.get_many_leq_kv(keys, fuzzy_bytes)
         }
     }
+
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        {
+            self.read()?.get_range_kv(start, end, start_after, limit)
+        }
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        {
+            self.read()?.get_range_kv_reverse(start, end, start_after, limit)
+        }
+    }
 }
 
 impl<KVQ: KVQBinaryStore> KVQBinaryStoreWriter for KVQImmutableStoreWrapper<KVQ> {
@@ -139,6 +165,12 @@ impl<KVQ: KVQBinaryStore> KVQBinaryStoreWriter for KVQImmutableStoreWrapper<KVQ>
             self.write()?.delete_many(keys)
         }
     }
+
+    fn write_batch(&mut self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        {
+            self.write()?.write_batch(batch)
+        }
+    }
 }
 
 
@@ -187,6 +219,13 @@ impl<KVQ: KVQBinaryStore> KVQBinaryStoreWriterImmutable for KVQImmutableStoreWra
             self.write()?.delete_many(keys)
         }
     }
+
+    // applied under one write lock, so readers never see part of the batch
+    fn imm_write_batch(&self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        {
+            self.write()?.write_batch(batch)
+        }
+    }
 }
 
-impl<KVQ: KVQBinaryStore> KVQBinaryStoreImmutable for KVQImmutableStoreWrapper<KVQ> {}
\ No newline at end of file
+impl<KVQ: KVQBinaryStore + Send + Sync> KVQBinaryStoreImmutable for KVQImmutableStoreWrapper<KVQ> {}
\ No newline at end of file
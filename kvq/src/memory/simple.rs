@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 use std::ops::Bound::Included;
 
+use crate::traits::get_range_scan_bounds;
 use crate::traits::KVQBinaryStoreReader;
 use crate::traits::KVQBinaryStoreWriter;
 use crate::traits::KVQPair;
@@ -24,13 +25,8 @@ impl KVQBinaryStoreReader for KVQSimpleMemoryBackingStore {
         }
     }
 
-    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
-        let mut result = Vec::new();
-        for key in keys {
-            let r = self.get_exact(key)?;
-            result.push(r);
-        }
-        Ok(result)
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        Ok(keys.iter().map(|key| self.map.get(key).cloned()).collect())
     }
 
     fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
@@ -136,48 +132,51 @@ impl KVQBinaryStoreReader for KVQSimpleMemoryBackingStore {
             Ok(None)
         }
     }
-    /*
 
     fn get_range_kv(
         &self,
-        min_included: &Vec<u8>,
-        max_included: &Vec<u8>,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
     ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
-        let rq = self
+        let bounds = match get_range_scan_bounds(start, end, start_after, false) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
+        Ok(self
             .map
-            .range((
-                Included(min_included.to_vec()),
-                Included(max_included.to_vec()),
-            ))
+            .range(bounds)
+            .take(limit)
             .map(|(k, v)| KVQPair {
                 key: k.to_owned(),
                 value: v.to_owned(),
             })
-            .collect::<Vec<_>>();
-        Ok(rq)
+            .collect::<Vec<_>>())
     }
 
-    fn get_prefix_range_kv(
+    fn get_range_kv_reverse(
         &self,
-        prefix: &Vec<u8>,
-        fuzzy_bytes: usize,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
     ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
-        let mut base_key = vec![0u8; prefix.len() + fuzzy_bytes];
-        base_key[0..prefix.len()].copy_from_slice(prefix);
-
-        let mut key_end = base_key.to_vec();
-        for i in ((prefix.len() - fuzzy_bytes)..prefix.len()) {
-            key_end[i] = 0xff;
-        }
+        let bounds = match get_range_scan_bounds(start, end, start_after, true) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
         Ok(self
             .map
-            .range((Included(base_key), Included(key_end)))
+            .range(bounds)
+            .rev()
+            .take(limit)
             .map(|(k, v)| KVQPair {
                 key: k.to_owned(),
                 value: v.to_owned(),
             })
             .collect::<Vec<_>>())
-    }*/
+    }
     
     fn get_fuzzy_range_leq_kv(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
         let key_end = key.to_vec();
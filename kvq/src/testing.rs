@@ -0,0 +1,888 @@
+//! A conformance suite for `KVQBinaryStore` implementations.
+//!
+//! Every read of the store is compared with `KVQReferenceStore`, a `BTreeMap` model whose reads are plain scans, so
+//! all stores agree on the edge cases (fuzzy suffixes that are all zero or longer than the key, keys of different
+//! lengths, empty values, range cursors and limits). A store passes with:
+//!
+//! ```ignore
+//! kvq::testing::check_store(&mut store)?;
+//! ```
+//!
+//! Only whether a write failed is compared: the value returned by `delete` and `delete_many` differs between stores
+//! (RocksDB cannot tell whether the key existed) and is not checked.
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use crate::traits::KVQBinaryStore;
+use crate::traits::KVQBinaryStoreReader;
+use crate::traits::KVQBinaryStoreWriter;
+use crate::traits::KVQPair;
+use crate::traits::KVQWriteBatch;
+use crate::traits::KVQWriteOp;
+
+/// Seeds of the randomized runs of `check_store`.
+pub const CONFORMANCE_SEEDS: [u64; 3] = [1, 0x5eed, 0xdeadbeef];
+/// Number of writes in each randomized run of `check_store`.
+pub const CONFORMANCE_STEPS: usize = 500;
+/// Number of random reads compared after every write of a randomized run.
+const READS_PER_STEP: usize = 4;
+
+type KVQBytePair = KVQPair<Vec<u8>, Vec<u8>>;
+
+/// The reference model: a `BTreeMap` whose reads filter every key, without range tricks.
+#[derive(Debug, Clone, Default)]
+pub struct KVQReferenceStore {
+    map: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl KVQReferenceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the keys between `key` with its last `fuzzy_bytes` bytes zeroed and `key`, ascending
+    fn fuzzy_range<'a>(
+        &'a self,
+        key: &'a [u8],
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)> + 'a> {
+        if fuzzy_bytes > key.len() {
+            anyhow::bail!("Fuzzy bytes must be less than or equal to key length");
+        }
+        let mut base_key = key.to_vec();
+        let key_len = base_key.len();
+        base_key[key_len - fuzzy_bytes..].fill(0);
+        Ok(self
+            .map
+            .iter()
+            .filter(move |(k, _)| k.as_slice() >= base_key.as_slice() && k.as_slice() <= key))
+    }
+}
+
+fn to_pair((key, value): (&Vec<u8>, &Vec<u8>)) -> KVQPair<Vec<u8>, Vec<u8>> {
+    KVQPair {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    }
+}
+
+impl KVQBinaryStoreReader for KVQReferenceStore {
+    fn get_exact_if_exists(&self, key: &Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.map.get(key).cloned())
+    }
+
+    fn get_exact(&self, key: &Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match self.map.get(key) {
+            Some(v) => Ok(v.to_owned()),
+            None => anyhow::bail!("Key {} not found", hex::encode(key)),
+        }
+    }
+
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get_exact_if_exists(key)).collect()
+    }
+
+    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
+        keys.iter().map(|key| self.get_exact(key)).collect()
+    }
+
+    fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.get_leq_kv(key, fuzzy_bytes)?.map(|kv| kv.value))
+    }
+
+    fn get_fuzzy_range_leq_kv(
+        &self,
+        key: &Vec<u8>,
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        Ok(self.fuzzy_range(key, fuzzy_bytes)?.map(to_pair).collect())
+    }
+
+    fn get_leq_kv(
+        &self,
+        key: &Vec<u8>,
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
+        Ok(self.fuzzy_range(key, fuzzy_bytes)?.last().map(to_pair))
+    }
+
+    fn get_many_leq(
+        &self,
+        keys: &[Vec<u8>],
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get_leq(key, fuzzy_bytes)).collect()
+    }
+
+    fn get_many_leq_kv(
+        &self,
+        keys: &[Vec<u8>],
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>>> {
+        keys.iter().map(|key| self.get_leq_kv(key, fuzzy_bytes)).collect()
+    }
+
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        Ok(self
+            .map
+            .iter()
+            .filter(|(k, _)| *k >= start && end.map_or(true, |e| *k < e))
+            .filter(|(k, _)| start_after.map_or(true, |c| *k > c))
+            .take(limit)
+            .map(to_pair)
+            .collect())
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        Ok(self
+            .map
+            .iter()
+            .rev()
+            .filter(|(k, _)| *k >= start && end.map_or(true, |e| *k < e))
+            .filter(|(k, _)| start_after.map_or(true, |c| *k < c))
+            .take(limit)
+            .map(to_pair)
+            .collect())
+    }
+
+    fn get_prefix_kv(
+        &self,
+        prefix: &Vec<u8>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        Ok(self
+            .map
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix) && start_after.map_or(true, |c| *k > c))
+            .take(limit)
+            .map(to_pair)
+            .collect())
+    }
+
+    fn get_prefix_kv_reverse(
+        &self,
+        prefix: &Vec<u8>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        Ok(self
+            .map
+            .iter()
+            .rev()
+            .filter(|(k, _)| k.starts_with(prefix) && start_after.map_or(true, |c| *k < c))
+            .take(limit)
+            .map(to_pair)
+            .collect())
+    }
+}
+
+impl KVQBinaryStoreWriter for KVQReferenceStore {
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    fn set_ref(&mut self, key: &Vec<u8>, value: &Vec<u8>) -> anyhow::Result<()> {
+        self.set(key.to_owned(), value.to_owned())
+    }
+
+    fn set_many_ref<'a>(
+        &mut self,
+        items: &[KVQPair<&'a Vec<u8>, &'a Vec<u8>>],
+    ) -> anyhow::Result<()> {
+        for item in items {
+            self.set_ref(item.key, item.value)?;
+        }
+        Ok(())
+    }
+
+    fn set_many_vec(&mut self, items: Vec<KVQPair<Vec<u8>, Vec<u8>>>) -> anyhow::Result<()> {
+        for item in items {
+            self.set(item.key, item.value)?;
+        }
+        Ok(())
+    }
+
+    fn set_many_split_ref(&mut self, keys: &[Vec<u8>], values: &[Vec<u8>]) -> anyhow::Result<()> {
+        if keys.len() != values.len() {
+            anyhow::bail!("Keys and values must have the same length");
+        }
+        for (key, value) in keys.iter().zip(values) {
+            self.set_ref(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &Vec<u8>) -> anyhow::Result<bool> {
+        Ok(self.map.remove(key).is_some())
+    }
+
+    fn delete_many(&mut self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>> {
+        keys.iter().map(|key| self.delete(key)).collect()
+    }
+}
+
+/// Runs every write on both the store and the reference model and compares every read. Reads return the store's
+/// result, or `None` if both failed.
+struct KVQConformanceHarness<'a, S: KVQBinaryStore> {
+    store: &'a mut S,
+    model: KVQReferenceStore,
+}
+
+impl<'a, S: KVQBinaryStore> KVQConformanceHarness<'a, S> {
+    fn new(store: &'a mut S) -> anyhow::Result<Self> {
+        if !store.get_range_kv(&Vec::new(), None, None, 1)?.is_empty() {
+            anyhow::bail!("the conformance suite must be run on an empty store");
+        }
+        Ok(Self {
+            store,
+            model: KVQReferenceStore::new(),
+        })
+    }
+
+    // an error from both the store and the model is a match, whatever the messages
+    fn read<R: PartialEq + Debug>(
+        &self,
+        name: &str,
+        f: impl Fn(&dyn KVQBinaryStoreReader) -> anyhow::Result<R>,
+    ) -> anyhow::Result<Option<R>> {
+        let actual = f(&*self.store);
+        let expected = f(&self.model);
+        match (actual, expected) {
+            (Ok(a), Ok(e)) if a == e => Ok(Some(a)),
+            (Err(_), Err(_)) => Ok(None),
+            (a, e) => anyhow::bail!("{}: store returned {:?}, reference returned {:?}", name, a, e),
+        }
+    }
+
+    fn write(
+        &mut self,
+        name: &str,
+        f: impl Fn(&mut dyn KVQBinaryStoreWriter) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let actual = f(&mut *self.store);
+        let expected = f(&mut self.model);
+        match (actual, expected) {
+            (Ok(()), Ok(())) | (Err(_), Err(_)) => Ok(()),
+            (a, e) => anyhow::bail!("{}: store returned {:?}, reference returned {:?}", name, a, e),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.write(&format!("set({})", hex::encode(&key)), |s| s.set_ref(&key, &value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> anyhow::Result<()> {
+        let key = key.to_vec();
+        self.write(&format!("delete({})", hex::encode(&key)), |s| s.delete(&key).map(|_| ()))
+    }
+
+    fn get_leq_kv(&self, key: &[u8], fuzzy_bytes: usize) -> anyhow::Result<Option<Option<KVQBytePair>>> {
+        let key = key.to_vec();
+        let name = format!("get_leq_kv({}, {})", hex::encode(&key), fuzzy_bytes);
+        self.read(&name, |s| s.get_leq(&key, fuzzy_bytes))?;
+        self.read(&name, |s| s.get_leq_kv(&key, fuzzy_bytes))
+    }
+
+    fn get_fuzzy_range_leq_kv(&self, key: &[u8], fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<KVQBytePair>>> {
+        let key = key.to_vec();
+        let name = format!("get_fuzzy_range_leq_kv({}, {})", hex::encode(&key), fuzzy_bytes);
+        self.read(&name, |s| s.get_fuzzy_range_leq_kv(&key, fuzzy_bytes))
+    }
+
+    fn get_exact_if_exists(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        let name = format!("get_exact({})", hex::encode(&key));
+        self.read(&name, |s| s.get_exact(&key))?;
+        Ok(self.read(&name, |s| s.get_exact_if_exists(&key))?.flatten())
+    }
+
+    fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        start_after: Option<&[u8]>,
+        limit: usize,
+        reverse: bool,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let start = start.to_vec();
+        let end = end.map(|e| e.to_vec());
+        let start_after = start_after.map(|c| c.to_vec());
+        let name = format!(
+            "get_range_kv{}({}, {:?}, {:?}, {})",
+            if reverse { "_reverse" } else { "" },
+            hex::encode(&start),
+            end.as_ref().map(hex::encode),
+            start_after.as_ref().map(hex::encode),
+            limit
+        );
+        let result = self.read(&name, |s| {
+            if reverse {
+                s.get_range_kv_reverse(&start, end.as_ref(), start_after.as_ref(), limit)
+            } else {
+                s.get_range_kv(&start, end.as_ref(), start_after.as_ref(), limit)
+            }
+        })?;
+        Ok(result.unwrap_or_default().into_iter().map(|kv| kv.key).collect())
+    }
+
+    fn get_prefix(&self, prefix: &[u8], start_after: Option<&[u8]>, limit: usize, reverse: bool) -> anyhow::Result<Vec<Vec<u8>>> {
+        let prefix = prefix.to_vec();
+        let start_after = start_after.map(|c| c.to_vec());
+        let name = format!(
+            "get_prefix_kv{}({}, {:?}, {})",
+            if reverse { "_reverse" } else { "" },
+            hex::encode(&prefix),
+            start_after.as_ref().map(hex::encode),
+            limit
+        );
+        let result = self.read(&name, |s| {
+            if reverse {
+                s.get_prefix_kv_reverse(&prefix, start_after.as_ref(), limit)
+            } else {
+                s.get_prefix_kv(&prefix, start_after.as_ref(), limit)
+            }
+        })?;
+        Ok(result.unwrap_or_default().into_iter().map(|kv| kv.key).collect())
+    }
+}
+
+fn expect<T: PartialEq + Debug>(name: &str, actual: T, expected: T) -> anyhow::Result<()> {
+    if actual != expected {
+        anyhow::bail!("{}: got {:?}, expected {:?}", name, actual, expected);
+    }
+    Ok(())
+}
+
+// a table key: 4 byte base followed by an 8 byte big endian block number
+fn versioned_key(base: u32, block_number: u64) -> Vec<u8> {
+    let mut key = base.to_be_bytes().to_vec();
+    key.extend_from_slice(&block_number.to_be_bytes());
+    key
+}
+
+/// Runs the edge case checks and the randomized runs (`CONFORMANCE_SEEDS`, `CONFORMANCE_STEPS` writes each) on an
+/// empty store. The store is left with the data written by the checks.
+pub fn check_store<S: KVQBinaryStore>(store: &mut S) -> anyhow::Result<()> {
+    let mut harness = KVQConformanceHarness::new(store)?;
+    check_edge_cases_with(&mut harness)?;
+    for seed in CONFORMANCE_SEEDS {
+        check_randomized_with(&mut harness, seed, CONFORMANCE_STEPS)
+            .map_err(|e| anyhow::anyhow!("randomized run with seed {:#x}: {}", seed, e))?;
+    }
+    Ok(())
+}
+
+/// Checks the documented edge cases of the reads on an empty store.
+pub fn check_edge_cases<S: KVQBinaryStore>(store: &mut S) -> anyhow::Result<()> {
+    check_edge_cases_with(&mut KVQConformanceHarness::new(store)?)
+}
+
+/// Applies `steps` random writes to an empty store, comparing random reads with the reference model after each one.
+pub fn check_randomized<S: KVQBinaryStore>(store: &mut S, seed: u64, steps: usize) -> anyhow::Result<()> {
+    check_randomized_with(&mut KVQConformanceHarness::new(store)?, seed, steps)
+}
+
+fn check_edge_cases_with<S: KVQBinaryStore>(h: &mut KVQConformanceHarness<S>) -> anyhow::Result<()> {
+    let base = 0x0000_0100;
+    let next_base = 0x0000_0101;
+
+    // missing keys
+    expect("get_exact_if_exists of a missing key", h.get_exact_if_exists(&versioned_key(base, 0))?, None)?;
+    expect("get_leq_kv on an empty store", h.get_leq_kv(&versioned_key(base, 9), 8)?, Some(None))?;
+
+    // versions of a fuzzy key, a neighbouring key and the bare base key, which sorts below every version
+    h.set(&versioned_key(base, 0), b"v0")?;
+    h.set(&versioned_key(base, 5), b"v5")?;
+    h.set(&versioned_key(base, 9), b"v9")?;
+    h.set(&versioned_key(next_base, 2), b"n2")?;
+    h.set(&base.to_be_bytes(), b"bare")?;
+    let version = |block_number: u64, value: &[u8]| {
+        Some(Some(KVQPair {
+            key: versioned_key(base, block_number),
+            value: value.to_vec(),
+        }))
+    };
+    // an all zero fuzzy suffix only matches the key itself
+    expect("get_leq_kv at block 0", h.get_leq_kv(&versioned_key(base, 0), 8)?, version(0, b"v0"))?;
+    expect("get_leq_kv at block 4", h.get_leq_kv(&versioned_key(base, 4), 8)?, version(0, b"v0"))?;
+    expect("get_leq_kv at block 5", h.get_leq_kv(&versioned_key(base, 5), 8)?, version(5, b"v5"))?;
+    expect("get_leq_kv at block 8", h.get_leq_kv(&versioned_key(base, 8), 8)?, version(5, b"v5"))?;
+    expect("get_leq_kv at block u64::MAX", h.get_leq_kv(&versioned_key(base, u64::MAX), 8)?, version(9, b"v9"))?;
+    expect(
+        "get_leq_kv of a key without versions at or below the block",
+        h.get_leq_kv(&versioned_key(next_base, 1), 8)?,
+        Some(None),
+    )?;
+    expect(
+        "get_fuzzy_range_leq_kv",
+        h.get_fuzzy_range_leq_kv(&versioned_key(base, 7), 8)?
+            .map(|kvs| kvs.into_iter().map(|kv| kv.value).collect::<Vec<_>>()),
+        Some(vec![b"v0".to_vec(), b"v5".to_vec()]),
+    )?;
+    // zero fuzzy bytes is an exact read, as many fuzzy bytes as the key has scans from the all zero key
+    expect("get_leq_kv without fuzzy bytes", h.get_leq_kv(&versioned_key(base, 7), 0)?, Some(None))?;
+    h.get_leq_kv(&versioned_key(base, 7), 12)?;
+    h.get_fuzzy_range_leq_kv(&versioned_key(next_base, 7), 12)?;
+    // more fuzzy bytes than the key has is an error
+    expect("get_leq_kv with fuzzy bytes > key length", h.get_leq_kv(&versioned_key(base, 7), 13)?, None)?;
+    expect(
+        "get_fuzzy_range_leq_kv with fuzzy bytes > key length",
+        h.get_fuzzy_range_leq_kv(&versioned_key(base, 7), 13)?,
+        None,
+    )?;
+    h.read("get_many_leq with fuzzy bytes > key length", |s| s.get_many_leq(&[versioned_key(base, 7)], 13))?;
+    h.read("get_many_leq_kv", |s| {
+        s.get_many_leq_kv(&[versioned_key(base, 7), versioned_key(next_base, 1), versioned_key(next_base, 3)], 8)
+    })?;
+
+    // deleting the newest version exposes the previous one
+    h.delete(&versioned_key(base, 5))?;
+    expect("get_leq_kv after deleting a version", h.get_leq_kv(&versioned_key(base, 8), 8)?, version(0, b"v0"))?;
+    // an empty value is a value, not a missing key
+    h.set(&versioned_key(base, 6), b"")?;
+    expect("get_exact_if_exists of an empty value", h.get_exact_if_exists(&versioned_key(base, 6))?, Some(Vec::new()))?;
+    expect("get_leq_kv of an empty value", h.get_leq_kv(&versioned_key(base, 8), 8)?, version(6, b""))?;
+    h.read("get_many_exact with a missing key", |s| s.get_many_exact(&[versioned_key(base, 6), versioned_key(base, 5)]))?;
+    h.read("get_many_exact", |s| s.get_many_exact(&[versioned_key(base, 6), versioned_key(base, 0)]))?;
+    expect(
+        "get_many_exact_if_exists",
+        h.read("get_many_exact_if_exists", |s| {
+            s.get_many_exact_if_exists(&[versioned_key(base, 5), versioned_key(base, 6), versioned_key(base, 5)])
+        })?,
+        Some(vec![None, Some(Vec::new()), None]),
+    )?;
+    h.read("get_many_exact_if_exists of no keys", |s| s.get_many_exact_if_exists(&[]))?;
+
+    // range scans: cursors outside the range, empty ranges and limits
+    let all = h.get_range(&[], None, None, usize::MAX, false)?;
+    expect("number of keys", all.len(), 5)?;
+    let start = versioned_key(base, 0);
+    let end = versioned_key(base, 9);
+    expect("get_range_kv", h.get_range(&start, Some(&end), None, usize::MAX, false)?.len(), 2)?;
+    expect("get_range_kv with limit 0", h.get_range(&start, Some(&end), None, 0, false)?, Vec::new())?;
+    expect("get_range_kv with end < start", h.get_range(&end, Some(&start), None, usize::MAX, false)?, Vec::new())?;
+    expect("get_range_kv with end = start", h.get_range(&start, Some(&start), None, usize::MAX, true)?, Vec::new())?;
+    h.get_range(&start, Some(&end), Some(&base.to_be_bytes()), usize::MAX, false)?;
+    h.get_range(&start, Some(&end), Some(&end), usize::MAX, false)?;
+    h.get_range(&start, Some(&end), Some(&start), 1, false)?;
+    h.get_range(&start, Some(&end), Some(&base.to_be_bytes()), usize::MAX, true)?;
+    h.get_range(&start, Some(&end), Some(&versioned_key(next_base, 0)), 1, true)?;
+    h.get_range(&start, None, Some(&end), usize::MAX, true)?;
+    h.get_range(&[], None, Some(&versioned_key(base, 6)), 2, true)?;
+
+    // prefix scans, including a prefix without an end key and the empty prefix
+    h.set(&[0xff, 0xff], b"ff")?;
+    h.set(&[0xff, 0xff, 0x00], b"ff00")?;
+    h.set(&[0xff, 0xfe, 0xff], b"fefe")?;
+    expect("get_prefix_kv of 0xffff", h.get_prefix(&[0xff, 0xff], None, usize::MAX, false)?.len(), 2)?;
+    h.get_prefix(&[0xff], Some(&[0xff, 0xff]), usize::MAX, false)?;
+    h.get_prefix(&[0xff], None, usize::MAX, true)?;
+    h.get_prefix(&[0xff], Some(&[0xff, 0xff]), usize::MAX, true)?;
+    expect("get_prefix_kv of the empty prefix", h.get_prefix(&[], None, usize::MAX, false)?.len(), 8)?;
+    h.get_prefix(&base.to_be_bytes(), Some(&versioned_key(base, 0)), 1, false)?;
+    h.get_prefix(&base.to_be_bytes(), None, 2, true)?;
+
+    // write batches are applied in order
+    let mut batch = KVQWriteBatch::new();
+    batch.set(vec![0x10], b"a".to_vec());
+    batch.delete(vec![0x10]);
+    batch.delete(vec![0x11]);
+    batch.set(vec![0x11], b"b".to_vec());
+    batch.set(vec![0x12], b"c".to_vec());
+    batch.set(vec![0x12], b"d".to_vec());
+    h.write("write_batch", |s| s.write_batch(batch.clone()))?;
+    expect("key set then deleted in a batch", h.get_exact_if_exists(&[0x10])?, None)?;
+    expect("key deleted then set in a batch", h.get_exact_if_exists(&[0x11])?, Some(b"b".to_vec()))?;
+    expect("key set twice in a batch", h.get_exact_if_exists(&[0x12])?, Some(b"d".to_vec()))?;
+
+    // the other write methods
+    let keys = vec![vec![0x20], vec![0x21], vec![0x20]];
+    let values = vec![b"x".to_vec(), b"y".to_vec(), b"z".to_vec()];
+    h.write("set_many_split_ref", |s| s.set_many_split_ref(&keys, &values))?;
+    h.write("set_many_split_ref with mismatched lengths", |s| s.set_many_split_ref(&keys, &values[..2]))?;
+    expect("key set twice in set_many_split_ref", h.get_exact_if_exists(&[0x20])?, Some(b"z".to_vec()))?;
+    h.write("delete_many", |s| s.delete_many(&[vec![0x20], vec![0x22]]).map(|_| ()))?;
+    h.write("set_many_vec", |s| {
+        s.set_many_vec(vec![KVQPair {
+            key: vec![0x22],
+            value: b"w".to_vec(),
+        }])
+    })?;
+    h.get_prefix(&[], None, usize::MAX, false)?;
+    Ok(())
+}
+
+/// xorshift64*, enough to draw keys and operations reproducibly without a dependency
+pub struct KVQTestRng(u64);
+
+impl KVQTestRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+
+    // mostly 2 byte bases with a 2 byte fuzzy suffix, drawn from few bytes so versions collide, and sometimes a
+    // shorter key that sorts between them
+    fn key(&mut self) -> Vec<u8> {
+        let mut key = vec![
+            self.pick(&[0x00, 0x7f, 0xff]),
+            self.pick(&[0x00, 0x01, 0xff]),
+            self.pick(&[0x00, 0x01, 0x80, 0xff]),
+            self.pick(&[0x00, 0x01, 0x80, 0xff]),
+        ];
+        if self.below(8) == 0 {
+            key.truncate(self.below(4));
+        }
+        key
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        (0..self.below(4)).map(|_| self.next() as u8).collect()
+    }
+
+    fn keys(&mut self) -> Vec<Vec<u8>> {
+        (0..self.below(4)).map(|_| self.key()).collect()
+    }
+
+    fn fuzzy_bytes(&mut self, key: &[u8]) -> usize {
+        match self.below(4) {
+            0 => self.below(key.len() + 2),
+            _ => 2,
+        }
+    }
+
+    fn limit(&mut self) -> usize {
+        self.pick(&[0, 1, 2, 5, usize::MAX])
+    }
+
+    fn cursor(&mut self) -> Option<Vec<u8>> {
+        if self.below(2) == 0 {
+            Some(self.key())
+        } else {
+            None
+        }
+    }
+}
+
+fn check_randomized_with<S: KVQBinaryStore>(h: &mut KVQConformanceHarness<S>, seed: u64, steps: usize) -> anyhow::Result<()> {
+    let mut rng = KVQTestRng::new(seed);
+    for step in 0..steps {
+        let result = random_write(h, &mut rng).and_then(|_| {
+            for _ in 0..READS_PER_STEP {
+                random_read(h, &mut rng)?;
+            }
+            Ok(())
+        });
+        result.map_err(|e| anyhow::anyhow!("step {}: {}", step, e))?;
+    }
+    // finish with a full scan so any divergence left by the last writes is caught
+    h.get_range(&[], None, None, usize::MAX, false)?;
+    Ok(())
+}
+
+fn random_write<S: KVQBinaryStore>(h: &mut KVQConformanceHarness<S>, rng: &mut KVQTestRng) -> anyhow::Result<()> {
+    match rng.below(8) {
+        0 | 1 => {
+            let (key, value) = (rng.key(), rng.value());
+            h.set(&key, &value)
+        }
+        2 => {
+            let (key, value) = (rng.key(), rng.value());
+            h.write("set", |s| s.set(key.clone(), value.clone()))
+        }
+        3 => {
+            let items = rng
+                .keys()
+                .into_iter()
+                .map(|key| KVQPair { key, value: rng.value() })
+                .collect::<Vec<_>>();
+            h.write("set_many_vec", |s| s.set_many_vec(items.clone()))?;
+            h.write("set_many_ref", |s| {
+                s.set_many_ref(&items.iter().map(|kv| KVQPair { key: &kv.key, value: &kv.value }).collect::<Vec<_>>())
+            })
+        }
+        4 => {
+            let keys = rng.keys();
+            // sometimes one value short, which must fail without writing anything
+            let value_count = keys.len().saturating_sub(rng.below(2));
+            let values = (0..value_count).map(|_| rng.value()).collect::<Vec<_>>();
+            h.write("set_many_split_ref", |s| s.set_many_split_ref(&keys, &values))
+        }
+        5 => {
+            let key = rng.key();
+            h.delete(&key)
+        }
+        6 => {
+            let keys = rng.keys();
+            h.write("delete_many", |s| s.delete_many(&keys).map(|_| ()))
+        }
+        _ => {
+            let mut batch = KVQWriteBatch::new();
+            for _ in 0..rng.below(6) {
+                if rng.below(3) == 0 {
+                    batch.delete(rng.key());
+                } else {
+                    batch.set(rng.key(), rng.value());
+                }
+            }
+            let name = format!("write_batch({:?})", batch.ops.iter().map(|op| match op {
+                KVQWriteOp::Set { key, .. } => format!("set {}", hex::encode(key)),
+                KVQWriteOp::Delete { key } => format!("delete {}", hex::encode(key)),
+            }).collect::<Vec<_>>());
+            h.write(&name, |s| s.write_batch(batch.clone()))
+        }
+    }
+}
+
+fn random_read<S: KVQBinaryStore>(h: &KVQConformanceHarness<S>, rng: &mut KVQTestRng) -> anyhow::Result<()> {
+    match rng.below(6) {
+        0 => {
+            h.get_exact_if_exists(&rng.key())?;
+        }
+        1 => {
+            let key = rng.key();
+            let fuzzy_bytes = rng.fuzzy_bytes(&key);
+            h.get_leq_kv(&key, fuzzy_bytes)?;
+        }
+        2 => {
+            let key = rng.key();
+            let fuzzy_bytes = rng.fuzzy_bytes(&key);
+            h.get_fuzzy_range_leq_kv(&key, fuzzy_bytes)?;
+        }
+        3 => {
+            let keys = rng.keys();
+            let fuzzy_bytes = rng.pick(&[0, 1, 2]);
+            h.read("get_many_leq", |s| s.get_many_leq(&keys, fuzzy_bytes))?;
+            h.read("get_many_leq_kv", |s| s.get_many_leq_kv(&keys, fuzzy_bytes))?;
+            h.read("get_many_exact", |s| s.get_many_exact(&keys))?;
+            h.read("get_many_exact_if_exists", |s| s.get_many_exact_if_exists(&keys))?;
+        }
+        4 => {
+            let start = rng.key();
+            let end = if rng.below(4) == 0 { None } else { Some(rng.key()) };
+            let cursor = rng.cursor();
+            let (limit, reverse) = (rng.limit(), rng.below(2) == 0);
+            h.get_range(&start, end.as_deref(), cursor.as_deref(), limit, reverse)?;
+        }
+        _ => {
+            let mut prefix = rng.key();
+            prefix.truncate(rng.below(4));
+            let cursor = rng.cursor();
+            let (limit, reverse) = (rng.limit(), rng.below(2) == 0);
+            h.get_prefix(&prefix, cursor.as_deref(), limit, reverse)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::check_randomized;
+    use super::check_store;
+    use crate::cache::KVQBinaryStoreCached;
+    use crate::cache::KVQBinaryStoreCachedTrait;
+    use crate::memory::immutable::KVQImmutableStoreWrapper;
+    use crate::memory::simple::KVQSimpleMemoryBackingStore;
+    use crate::traits::KVQBinaryStoreReader;
+    use crate::traits::KVQBinaryStoreWriter;
+    use crate::traits::KVQPair;
+
+    type KVQMemoryStore = KVQImmutableStoreWrapper<KVQSimpleMemoryBackingStore>;
+
+    fn new_cached_store() -> KVQBinaryStoreCached<KVQMemoryStore> {
+        KVQBinaryStoreCached::new(Arc::new(KVQImmutableStoreWrapper::new(KVQSimpleMemoryBackingStore::new())))
+    }
+
+    // a cached store whose pending writes are flushed to the backing store every `flush_every` writes, so reads
+    // merge cached and saved versions of the same keys
+    struct KVQFlushingCachedStore {
+        cached: KVQBinaryStoreCached<KVQMemoryStore>,
+        writes: usize,
+        flush_every: usize,
+    }
+
+    impl KVQFlushingCachedStore {
+        fn new(flush_every: usize) -> Self {
+            Self {
+                cached: new_cached_store(),
+                writes: 0,
+                flush_every,
+            }
+        }
+
+        fn after_write<R>(&mut self, result: anyhow::Result<R>) -> anyhow::Result<R> {
+            self.writes += 1;
+            if self.writes % self.flush_every == 0 {
+                self.cached.flush_simple()?;
+            }
+            result
+        }
+    }
+
+    impl KVQBinaryStoreReader for KVQFlushingCachedStore {
+        fn get_exact_if_exists(&self, key: &Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+            self.cached.get_exact_if_exists(key)
+        }
+
+        fn get_exact(&self, key: &Vec<u8>) -> anyhow::Result<Vec<u8>> {
+            self.cached.get_exact(key)
+        }
+
+        fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+            self.cached.get_many_exact_if_exists(keys)
+        }
+
+        fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
+            self.cached.get_leq(key, fuzzy_bytes)
+        }
+
+        fn get_fuzzy_range_leq_kv(
+            &self,
+            key: &Vec<u8>,
+            fuzzy_bytes: usize,
+        ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+            self.cached.get_fuzzy_range_leq_kv(key, fuzzy_bytes)
+        }
+
+        fn get_leq_kv(
+            &self,
+            key: &Vec<u8>,
+            fuzzy_bytes: usize,
+        ) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
+            self.cached.get_leq_kv(key, fuzzy_bytes)
+        }
+
+        fn get_many_leq(
+            &self,
+            keys: &[Vec<u8>],
+            fuzzy_bytes: usize,
+        ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+            self.cached.get_many_leq(keys, fuzzy_bytes)
+        }
+
+        fn get_many_leq_kv(
+            &self,
+            keys: &[Vec<u8>],
+            fuzzy_bytes: usize,
+        ) -> anyhow::Result<Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>>> {
+            self.cached.get_many_leq_kv(keys, fuzzy_bytes)
+        }
+
+        fn get_range_kv(
+            &self,
+            start: &Vec<u8>,
+            end: Option<&Vec<u8>>,
+            start_after: Option<&Vec<u8>>,
+            limit: usize,
+        ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+            self.cached.get_range_kv(start, end, start_after, limit)
+        }
+
+        fn get_range_kv_reverse(
+            &self,
+            start: &Vec<u8>,
+            end: Option<&Vec<u8>>,
+            start_after: Option<&Vec<u8>>,
+            limit: usize,
+        ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+            self.cached.get_range_kv_reverse(start, end, start_after, limit)
+        }
+    }
+
+    impl KVQBinaryStoreWriter for KVQFlushingCachedStore {
+        fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
+            let result = self.cached.set(key, value);
+            self.after_write(result)
+        }
+
+        fn set_ref(&mut self, key: &Vec<u8>, value: &Vec<u8>) -> anyhow::Result<()> {
+            let result = self.cached.set_ref(key, value);
+            self.after_write(result)
+        }
+
+        fn set_many_ref<'a>(&mut self, items: &[KVQPair<&'a Vec<u8>, &'a Vec<u8>>]) -> anyhow::Result<()> {
+            let result = self.cached.set_many_ref(items);
+            self.after_write(result)
+        }
+
+        fn set_many_vec(&mut self, items: Vec<KVQPair<Vec<u8>, Vec<u8>>>) -> anyhow::Result<()> {
+            let result = self.cached.set_many_vec(items);
+            self.after_write(result)
+        }
+
+        fn set_many_split_ref(&mut self, keys: &[Vec<u8>], values: &[Vec<u8>]) -> anyhow::Result<()> {
+            let result = self.cached.set_many_split_ref(keys, values);
+            self.after_write(result)
+        }
+
+        fn delete(&mut self, key: &Vec<u8>) -> anyhow::Result<bool> {
+            let result = self.cached.delete(key);
+            self.after_write(result)
+        }
+
+        fn delete_many(&mut self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>> {
+            let result = self.cached.delete_many(keys);
+            self.after_write(result)
+        }
+    }
+
+    #[test]
+    fn simple_memory_store_conforms() {
+        check_store(&mut KVQSimpleMemoryBackingStore::new()).unwrap();
+    }
+
+    #[test]
+    fn immutable_memory_store_conforms() {
+        check_store(&mut KVQImmutableStoreWrapper::new(KVQSimpleMemoryBackingStore::new())).unwrap();
+    }
+
+    #[test]
+    fn cached_store_conforms() {
+        check_store(&mut new_cached_store()).unwrap();
+    }
+
+    #[test]
+    fn cached_store_with_saved_versions_conforms() {
+        for flush_every in [1, 2, 3, 7, 50] {
+            check_store(&mut KVQFlushingCachedStore::new(flush_every))
+                .map_err(|e| anyhow::anyhow!("flushing every {} writes: {}", flush_every, e))
+                .unwrap();
+            for seed in 100..104 {
+                check_randomized(&mut KVQFlushingCachedStore::new(flush_every), seed, 1000)
+                    .map_err(|e| anyhow::anyhow!("flushing every {} writes, seed {}: {}", flush_every, seed, e))
+                    .unwrap();
+            }
+        }
+    }
+}
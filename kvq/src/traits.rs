@@ -1,6 +1,11 @@
+use std::ops::Bound;
+use std::ops::Bound::Excluded;
+use std::ops::Bound::Included;
+use std::ops::Bound::Unbounded;
+
 use serde::Deserialize;
 use serde::Serialize;
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct KVQPair<K, V> {
     pub key: K,
     pub value: V,
@@ -36,6 +41,40 @@ impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for KVQPair
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum KVQWriteOp {
+    Set { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+}
+
+/// Writes that are applied in order and committed atomically: either all of them are visible or none are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KVQWriteBatch {
+    pub ops: Vec<KVQWriteOp>,
+}
+impl KVQWriteBatch {
+    pub fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            ops: Vec::with_capacity(capacity),
+        }
+    }
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push(KVQWriteOp::Set { key, value });
+    }
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push(KVQWriteOp::Delete { key });
+    }
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
 pub trait KVQSerializable: Clone + PartialEq {
     fn to_bytes(&self) -> anyhow::Result<Vec<u8>>;
     fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self>;
@@ -61,10 +100,80 @@ pub fn unwrap_kv_result<T>(item_opt: Option<T>) -> anyhow::Result<T> {
     }
 }
 
+/// Returns the smallest key that is greater than every key starting with `prefix`,
+/// or `None` if no such key exists (empty or all 0xff prefix).
+pub fn get_prefix_end_key(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last != 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Returns `key` with its last `fuzzy_bytes` bytes zeroed: the first key of the range searched by the LEQ reads,
+/// which return the greatest key between it and `key`.
+pub fn get_fuzzy_base_key(key: &[u8], fuzzy_bytes: usize) -> anyhow::Result<Vec<u8>> {
+    let key_len = key.len();
+    if fuzzy_bytes > key_len {
+        return Err(anyhow::anyhow!(
+            "Fuzzy bytes must be less than or equal to key length"
+        ));
+    }
+    let mut base_key = key.to_vec();
+    base_key[key_len - fuzzy_bytes..].fill(0);
+    Ok(base_key)
+}
+
+/// Returns the smallest key greater than `key`, so a range that ends before it (excluded) includes `key`.
+pub fn get_inclusive_end_key(key: &[u8]) -> Vec<u8> {
+    let mut end = key.to_vec();
+    end.push(0);
+    end
+}
+
+/// Resolves a `start..end` range (start included, end excluded, `None` for unbounded) and an
+/// optional start-after cursor into the bounds that remain to be scanned.
+/// For forward scans the cursor is the last key already returned, for reverse scans it is the
+/// last (smallest) key already returned. Returns `None` if the remaining range is empty.
+pub fn get_range_scan_bounds(
+    start: &Vec<u8>,
+    end: Option<&Vec<u8>>,
+    start_after: Option<&Vec<u8>>,
+    reverse: bool,
+) -> Option<(Bound<Vec<u8>>, Bound<Vec<u8>>)> {
+    let mut lower = Included(start.to_vec());
+    let mut upper = match end {
+        Some(e) => Excluded(e.to_vec()),
+        None => Unbounded,
+    };
+    if let Some(cursor) = start_after {
+        if reverse {
+            if end.map_or(true, |e| cursor < e) {
+                upper = Excluded(cursor.to_vec());
+            }
+        } else if cursor >= start {
+            lower = Excluded(cursor.to_vec());
+        }
+    }
+    let is_empty = match (&lower, &upper) {
+        (Included(l), Excluded(u)) | (Excluded(l), Excluded(u)) => l >= u,
+        _ => false,
+    };
+    if is_empty {
+        None
+    } else {
+        Some((lower, upper))
+    }
+}
+
 pub trait KVQStoreAdapterReader<S, K: KVQSerializable, V: KVQSerializable> {
     fn get_exact_if_exists(s: &S, key: &K) -> anyhow::Result<Option<V>>;
     fn get_exact(s: &S, key: &K) -> anyhow::Result<V>;
     fn get_many_exact(s: &S, keys: &[K]) -> anyhow::Result<Vec<V>>;
+    fn get_many_exact_if_exists(s: &S, keys: &[K]) -> anyhow::Result<Vec<Option<V>>>;
 
     fn get_fuzzy_range_leq_kv(s: &S, key: &K, fuzzy_bytes: usize) -> anyhow::Result<Vec<KVQPair<K, V>>>;
     fn get_leq(s: &S, key: &K, fuzzy_bytes: usize) -> anyhow::Result<Option<V>>;
@@ -136,7 +245,15 @@ pub trait KVQStoreAdapterWithHelpers<S, K: KVQSerializable, V: KVQSerializable>:
 pub trait KVQBinaryStoreReader {
     fn get_exact_if_exists(&self, key: &Vec<u8>) -> anyhow::Result<Option<Vec<u8>>>;
     fn get_exact(&self, key: &Vec<u8>) -> anyhow::Result<Vec<u8>>;
-    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>>;
+    /// Returns the value of each key, `None` for missing keys, reading them in one batch where the store can.
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>>;
+    /// Returns the value of each key, failing if any of them is missing.
+    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
+        keys.iter()
+            .zip(self.get_many_exact_if_exists(keys)?)
+            .map(|(key, value)| value.ok_or_else(|| anyhow::anyhow!("Key {} not found", hex::encode(key))))
+            .collect()
+    }
 
     fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>>;
     fn get_fuzzy_range_leq_kv(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>>;
@@ -157,6 +274,44 @@ pub trait KVQBinaryStoreReader {
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>>>;
 
+    /// Returns up to `limit` pairs with `start <= key < end` in ascending key order,
+    /// skipping every key `<= start_after` when a cursor is given.
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>>;
+
+    /// Returns up to `limit` pairs with `start <= key < end` in descending key order,
+    /// skipping every key `>= start_after` when a cursor is given.
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>>;
+
+    fn get_prefix_kv(
+        &self,
+        prefix: &Vec<u8>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.get_range_kv(prefix, get_prefix_end_key(prefix).as_ref(), start_after, limit)
+    }
+
+    fn get_prefix_kv_reverse(
+        &self,
+        prefix: &Vec<u8>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.get_range_kv_reverse(prefix, get_prefix_end_key(prefix).as_ref(), start_after, limit)
+    }
+
     fn get_leq_u(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Vec<u8>> {
         unwrap_kv_result(self.get_leq(key, fuzzy_bytes)?)
     }
@@ -192,6 +347,20 @@ pub trait KVQBinaryStoreWriter {
 
     fn delete(&mut self, key: &Vec<u8>) -> anyhow::Result<bool>;
     fn delete_many(&mut self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>>;
+
+    /// Applies the writes of `batch` in order. Stores that persist each write on its own must override this to
+    /// commit the batch atomically.
+    fn write_batch(&mut self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        for op in batch.ops {
+            match op {
+                KVQWriteOp::Set { key, value } => self.set(key, value)?,
+                KVQWriteOp::Delete { key } => {
+                    self.delete(&key)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 
@@ -207,6 +376,9 @@ pub trait KVQBinaryStoreWriterImmutable {
 
     fn imm_delete(&self, key: &Vec<u8>) -> anyhow::Result<bool>;
     fn imm_delete_many(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>>;
+
+    /// Applies the writes of `batch` in order, atomically.
+    fn imm_write_batch(&self, batch: KVQWriteBatch) -> anyhow::Result<()>;
 }
 pub trait KVQBinaryStoreWriterAutoImmutable: KVQBinaryStoreWriterImmutable {}
 impl<T: KVQBinaryStoreWriterAutoImmutable> KVQBinaryStoreWriter for T {
@@ -240,10 +412,14 @@ impl<T: KVQBinaryStoreWriterAutoImmutable> KVQBinaryStoreWriter for T {
     fn delete_many(&mut self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>> {
         self.imm_delete_many(keys)
     }
+
+    fn write_batch(&mut self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        self.imm_write_batch(batch)
+    }
 }
 
 
 pub trait KVQBinaryStore: KVQBinaryStoreReader + KVQBinaryStoreWriter {}
-pub trait KVQBinaryStoreImmutable: KVQBinaryStore + KVQBinaryStoreWriterImmutable {}
+pub trait KVQBinaryStoreImmutable: KVQBinaryStore + KVQBinaryStoreWriterImmutable + Send + Sync {}
 
 impl<T: KVQBinaryStoreReader + KVQBinaryStoreWriter> KVQBinaryStore for T {}
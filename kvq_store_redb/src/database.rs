@@ -0,0 +1,179 @@
+use std::path::Path;
+
+use kvq::traits::KVQBinaryStoreImmutable;
+use kvq::traits::KVQBinaryStoreReader;
+use kvq::traits::KVQBinaryStoreWriter;
+use kvq::traits::KVQBinaryStoreWriterAutoImmutable;
+use kvq::traits::KVQBinaryStoreWriterImmutable;
+use kvq::traits::KVQPair;
+use kvq::traits::KVQWriteBatch;
+use redb::backends::InMemoryBackend;
+use redb::Database;
+use redb::ReadOnlyTable;
+use redb::Table;
+use redb::TableDefinition;
+
+use crate::KVQReDBStore;
+
+const KVQ_TABLE: TableDefinition<&'static [u8], &'static [u8]> = TableDefinition::new("kvq");
+
+/// A store that owns a redb database. Every read opens its own read transaction and every write (or `imm_write_batch`)
+/// is committed in its own write transaction, so the store can be shared between threads behind an `Arc`.
+pub struct KVQReDBDatabaseStore {
+    db: Database,
+}
+
+impl KVQReDBDatabaseStore {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::new(Database::create(path)?)
+    }
+
+    /// Creates an empty database kept in memory.
+    pub fn in_memory() -> anyhow::Result<Self> {
+        Self::new(Database::builder().create_with_backend(InMemoryBackend::new())?)
+    }
+
+    pub fn new(db: Database) -> anyhow::Result<Self> {
+        // create the table up front so read transactions can always open it
+        let txn = db.begin_write()?;
+        txn.open_table(KVQ_TABLE)?;
+        txn.commit()?;
+        Ok(Self { db })
+    }
+
+    fn read<R>(
+        &self,
+        f: impl FnOnce(&KVQReDBStore<ReadOnlyTable<&'static [u8], &'static [u8]>>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let txn = self.db.begin_read()?;
+        let store = KVQReDBStore::new(txn.open_table(KVQ_TABLE)?);
+        f(&store)
+    }
+
+    fn write<R>(
+        &self,
+        f: impl FnOnce(&mut KVQReDBStore<Table<&'static [u8], &'static [u8]>>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let txn = self.db.begin_write()?;
+        let result = {
+            let mut store = KVQReDBStore::new(txn.open_table(KVQ_TABLE)?);
+            f(&mut store)?
+        };
+        txn.commit()?;
+        Ok(result)
+    }
+}
+
+impl KVQBinaryStoreReader for KVQReDBDatabaseStore {
+    fn get_exact_if_exists(&self, key: &Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read(|store| store.get_exact_if_exists(key))
+    }
+
+    fn get_exact(&self, key: &Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.read(|store| store.get_exact(key))
+    }
+
+    // one read transaction for the whole batch
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.read(|store| store.get_many_exact_if_exists(keys))
+    }
+
+    fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
+        self.read(|store| store.get_leq(key, fuzzy_bytes))
+    }
+
+    fn get_fuzzy_range_leq_kv(
+        &self,
+        key: &Vec<u8>,
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.read(|store| store.get_fuzzy_range_leq_kv(key, fuzzy_bytes))
+    }
+
+    fn get_leq_kv(
+        &self,
+        key: &Vec<u8>,
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.read(|store| store.get_leq_kv(key, fuzzy_bytes))
+    }
+
+    fn get_many_leq(
+        &self,
+        keys: &[Vec<u8>],
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.read(|store| store.get_many_leq(keys, fuzzy_bytes))
+    }
+
+    fn get_many_leq_kv(
+        &self,
+        keys: &[Vec<u8>],
+        fuzzy_bytes: usize,
+    ) -> anyhow::Result<Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>>> {
+        self.read(|store| store.get_many_leq_kv(keys, fuzzy_bytes))
+    }
+
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.read(|store| store.get_range_kv(start, end, start_after, limit))
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        self.read(|store| store.get_range_kv_reverse(start, end, start_after, limit))
+    }
+}
+
+impl KVQBinaryStoreWriterImmutable for KVQReDBDatabaseStore {
+    fn imm_set(&self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
+        self.write(|store| store.set(key, value))
+    }
+
+    fn imm_set_ref(&self, key: &Vec<u8>, value: &Vec<u8>) -> anyhow::Result<()> {
+        self.write(|store| store.set_ref(key, value))
+    }
+
+    fn imm_set_many_ref<'a>(
+        &self,
+        items: &[KVQPair<&'a Vec<u8>, &'a Vec<u8>>],
+    ) -> anyhow::Result<()> {
+        self.write(|store| store.set_many_ref(items))
+    }
+
+    fn imm_set_many_vec(&self, items: Vec<KVQPair<Vec<u8>, Vec<u8>>>) -> anyhow::Result<()> {
+        self.write(|store| store.set_many_vec(items))
+    }
+
+    fn imm_set_many_split_ref(&self, keys: &[Vec<u8>], values: &[Vec<u8>]) -> anyhow::Result<()> {
+        self.write(|store| store.set_many_split_ref(keys, values))
+    }
+
+    fn imm_delete(&self, key: &Vec<u8>) -> anyhow::Result<bool> {
+        self.write(|store| store.delete(key))
+    }
+
+    fn imm_delete_many(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>> {
+        self.write(|store| store.delete_many(keys))
+    }
+
+    // the transaction is dropped (aborted) if any write fails
+    fn imm_write_batch(&self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        self.write(|store| store.write_batch(batch))
+    }
+}
+
+impl KVQBinaryStoreWriterAutoImmutable for KVQReDBDatabaseStore {}
+
+impl KVQBinaryStoreImmutable for KVQReDBDatabaseStore {}
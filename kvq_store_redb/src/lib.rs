@@ -1,9 +1,16 @@
+use std::ops::Bound;
+
+use kvq::traits::get_range_scan_bounds;
 use kvq::traits::KVQBinaryStoreReader;
 use kvq::traits::KVQBinaryStoreWriter;
 use kvq::traits::KVQPair;
 use redb::ReadableTable;
 use redb::Table;
 
+pub mod database;
+
+/// A store over a redb table. Writes go to the table's write transaction, so a `write_batch` becomes visible
+/// atomically when the transaction is committed.
 pub struct KVQReDBStore<T> {
     kv: T,
 }
@@ -27,13 +34,10 @@ where
         Ok(res)
     }
 
-    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
-        let mut result = Vec::new();
-        for key in keys {
-            let r = self.get_exact(key)?;
-            result.push(r);
-        }
-        Ok(result)
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        keys.iter()
+            .map(|key| Ok(self.kv.get(key.as_slice())?.map(|v| v.value().to_vec())))
+            .collect()
     }
 
     fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
@@ -157,6 +161,63 @@ where
             })
             .collect::<Result<Vec<_>, _>>()
     }
+
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        let (lower, upper) = match get_range_scan_bounds(start, end, start_after, false) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
+        self.kv
+            .range::<&[u8]>((as_slice_bound(&lower), as_slice_bound(&upper)))?
+            .take(limit)
+            .map(|x| {
+                let x = x?;
+                Ok(KVQPair {
+                    key: x.0.value().to_vec(),
+                    value: x.1.value().to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        let (lower, upper) = match get_range_scan_bounds(start, end, start_after, true) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
+        self.kv
+            .range::<&[u8]>((as_slice_bound(&lower), as_slice_bound(&upper)))?
+            .rev()
+            .take(limit)
+            .map(|x| {
+                let x = x?;
+                Ok(KVQPair {
+                    key: x.0.value().to_vec(),
+                    value: x.1.value().to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+    }
+}
+
+fn as_slice_bound(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(k) => Bound::Included(k.as_slice()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_slice()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
 }
 
 impl<'db, 'txn> KVQBinaryStoreWriter
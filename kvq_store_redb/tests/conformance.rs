@@ -0,0 +1,28 @@
+use kvq_store_redb::database::KVQReDBDatabaseStore;
+use kvq_store_redb::KVQReDBStore;
+use redb::backends::InMemoryBackend;
+use redb::Database;
+use redb::TableDefinition;
+
+#[test]
+fn database_store_conforms() {
+    kvq::testing::check_store(&mut KVQReDBDatabaseStore::in_memory().unwrap()).unwrap();
+}
+
+#[test]
+fn database_store_on_disk_conforms() {
+    let path = std::env::temp_dir().join(format!("kvq_redb_conformance_{}.redb", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let result = kvq::testing::check_store(&mut KVQReDBDatabaseStore::open(&path).unwrap());
+    let _ = std::fs::remove_file(&path);
+    result.unwrap();
+}
+
+#[test]
+fn table_store_conforms() {
+    let table: TableDefinition<&'static [u8], &'static [u8]> = TableDefinition::new("kvq");
+    let db = Database::builder().create_with_backend(InMemoryBackend::new()).unwrap();
+    let txn = db.begin_write().unwrap();
+    let mut store = KVQReDBStore::new(txn.open_table(table).unwrap());
+    kvq::testing::check_store(&mut store).unwrap();
+}
@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use kvq::traits::KVQBinaryStoreReader;
+use kvq::traits::KVQBinaryStoreWriterImmutable;
+use kvq::traits::KVQWriteBatch;
+use kvq_store_rocksdb::KVQRocksDBStore;
+
+/// Number of hot keys, each written at every block.
+const HOT_KEYS: u32 = 64;
+/// Number of versions (blocks) of each hot key.
+const VERSION_COUNTS: [u64; 2] = [1_000, 10_000];
+
+// laid out like a fuzzy block indexed table: a 4 byte table header, a 32 byte key and an 8 byte block number
+fn versioned_key(key_index: u32, block_number: u64) -> Vec<u8> {
+    let mut key = 0x100u32.to_be_bytes().to_vec();
+    key.extend_from_slice(&[0u8; 28]);
+    key.extend_from_slice(&key_index.to_be_bytes());
+    key.extend_from_slice(&block_number.to_be_bytes());
+    key
+}
+
+fn open_store(versions: u64) -> (KVQRocksDBStore, PathBuf) {
+    let path = std::env::temp_dir().join(format!("kvq_rocksdb_leq_bench_{}_{}", versions, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let store = KVQRocksDBStore::open_default(&path).unwrap();
+    for block_number in (0..versions).step_by(100) {
+        let mut batch = KVQWriteBatch::new();
+        for b in block_number..(block_number + 100).min(versions) {
+            for key_index in 0..HOT_KEYS {
+                batch.set(versioned_key(key_index, b), b.to_be_bytes().to_vec());
+            }
+        }
+        store.imm_write_batch(batch).unwrap();
+    }
+    (store, path)
+}
+
+fn bench_leq(c: &mut Criterion) {
+    for versions in VERSION_COUNTS {
+        let (store, path) = open_store(versions);
+        let mut group = c.benchmark_group(format!("leq_{}_versions", versions));
+        for (name, block_number) in [("newest", versions - 1), ("middle", versions / 2), ("oldest", 0)] {
+            let key = versioned_key(HOT_KEYS / 2, block_number);
+            group.bench_with_input(BenchmarkId::new("get_leq_kv", name), &key, |b, key| {
+                b.iter(|| store.get_leq_kv(black_box(key), 8).unwrap())
+            });
+        }
+        let keys = (0..HOT_KEYS)
+            .map(|key_index| versioned_key(key_index, versions - 1))
+            .collect::<Vec<_>>();
+        group.bench_with_input(BenchmarkId::new("get_many_leq_kv", HOT_KEYS), &keys, |b, keys| {
+            b.iter(|| store.get_many_leq_kv(black_box(keys), 8).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("get_leq_kv_each", HOT_KEYS), &keys, |b, keys| {
+            b.iter(|| {
+                keys.iter()
+                    .map(|key| store.get_leq_kv(black_box(key), 8).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.finish();
+        drop(store);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
+
+criterion_group!(benches, bench_leq);
+criterion_main!(benches);
@@ -1,12 +1,23 @@
 use std::path::Path;
 use std::sync::Arc;
 
+use std::ops::Bound;
+
+use kvq::traits::get_fuzzy_base_key;
+use kvq::traits::get_inclusive_end_key;
+use kvq::traits::get_range_scan_bounds;
 use kvq::traits::KVQBinaryStoreImmutable;
 use kvq::traits::KVQBinaryStoreReader;
 use kvq::traits::KVQBinaryStoreWriterAutoImmutable;
 use kvq::traits::KVQBinaryStoreWriterImmutable;
 use kvq::traits::KVQPair;
+use kvq::traits::KVQWriteBatch;
+use kvq::traits::KVQWriteOp;
+use rocksdb::DBRawIterator;
+use rocksdb::Direction;
 use rocksdb::ErrorKind;
+use rocksdb::IteratorMode;
+use rocksdb::ReadOptions;
 pub mod compat;
 #[derive(Clone)]
 pub struct KVQRocksDBStore {
@@ -38,21 +49,47 @@ impl KVQRocksDBStore {
         Ok(db)
     }
 }
-fn compare_u8_array_a_le_b(a: &[u8], b: &[u8]) -> bool {
-    return a <= b;
-    /*
-    if a.len() != b.len() {
-        return a.len() < b.len();
-    }else{
-        for i in 0..a.len() {
-            if a[i] != b[i] {
-                return a[i] < b[i];
-            }
+// positions `iter` on the greatest key <= `key` and returns it unless it is below `base_key`
+fn seek_leq(
+    iter: &mut DBRawIterator,
+    base_key: &[u8],
+    key: &[u8],
+) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
+    iter.seek_for_prev(key);
+    match (iter.key(), iter.value()) {
+        (Some(k), Some(v)) if k >= base_key => Ok(Some(KVQPair {
+            key: k.to_vec(),
+            value: v.to_vec(),
+        })),
+        (Some(_), Some(_)) => Ok(None),
+        _ => {
+            iter.status()?;
+            Ok(None)
         }
-        return false;
-    }*/
+    }
+}
 
+// bounds an iterator to the fuzzy range `base_key..=key`, so RocksDB skips everything outside of it
+fn fuzzy_range_read_options(base_key: &[u8], key: &[u8]) -> ReadOptions {
+    let mut read_opts = ReadOptions::default();
+    read_opts.set_iterate_lower_bound(base_key.to_vec());
+    read_opts.set_iterate_upper_bound(get_inclusive_end_key(key));
+    read_opts
+}
 
+fn is_above_lower_bound(key: &[u8], lower: &Bound<Vec<u8>>) -> bool {
+    match lower {
+        Bound::Included(l) => key >= l.as_slice(),
+        Bound::Excluded(l) => key > l.as_slice(),
+        Bound::Unbounded => true,
+    }
+}
+fn is_below_upper_bound(key: &[u8], upper: &Bound<Vec<u8>>) -> bool {
+    match upper {
+        Bound::Included(u) => key <= u.as_slice(),
+        Bound::Excluded(u) => key < u.as_slice(),
+        Bound::Unbounded => true,
+    }
 }
 
 impl KVQBinaryStoreReader for KVQRocksDBStore {
@@ -63,42 +100,16 @@ impl KVQBinaryStoreReader for KVQRocksDBStore {
         }
     }
 
-    fn get_many_exact(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
-        let mut result = Vec::new();
-        for key in keys {
-            let r = self.get_exact(key)?;
-            result.push(r);
-        }
-        Ok(result)
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.db
+            .multi_get(keys)
+            .into_iter()
+            .map(|r| Ok(r?))
+            .collect()
     }
 
     fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
-        let key_end = key.to_vec();
-        let mut base_key = key.to_vec();
-        let key_len = base_key.len();
-        if fuzzy_bytes > key_len {
-            return Err(anyhow::anyhow!(
-                "Fuzzy bytes must be less than or equal to key length"
-            ));
-        }
-
-        for i in 0..fuzzy_bytes {
-            base_key[key_len - i - 1] = 0;
-        }
-
-        let rq = self
-            .db
-            .prefix_iterator(base_key)
-            .take_while(|v| match v {
-                Ok((k, _)) if compare_u8_array_a_le_b(k.as_ref(), &key_end) => true,
-                _ => false,
-            })
-            .last();
-
-        match rq {
-            Some(Ok((_, v))) => Ok(Some(v.to_vec())),
-            _ => Ok(None),
-        }
+        Ok(self.get_leq_kv(key, fuzzy_bytes)?.map(|kv| kv.value))
     }
 
     fn get_leq_kv(
@@ -106,35 +117,11 @@ impl KVQBinaryStoreReader for KVQRocksDBStore {
         key: &Vec<u8>,
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
-        let key_end = key.to_vec();
-        let mut base_key = key.to_vec();
-        let key_len = base_key.len();
-        if fuzzy_bytes > key_len {
-            return Err(anyhow::anyhow!(
-                "Fuzzy bytes must be less than or equal to key length"
-            ));
-        }
-
-        for i in 0..fuzzy_bytes {
-            base_key[key_len - i - 1] = 0;
-        }
-
-        let rq = self
+        let base_key = get_fuzzy_base_key(key, fuzzy_bytes)?;
+        let mut iter = self
             .db
-            .prefix_iterator(base_key)
-            .take_while(|v| match v {
-                Ok((k, _)) if compare_u8_array_a_le_b(k.as_ref(), &key_end) => true,
-                _ => false,
-            })
-            .last();
-
-        match rq {
-            Some(Ok((k, v))) => Ok(Some(KVQPair {
-                key: k.to_vec(),
-                value: v.to_vec(),
-            })),
-            _ => Ok(None),
-        }
+            .raw_iterator_opt(fuzzy_range_read_options(&base_key, key));
+        seek_leq(&mut iter, &base_key, key)
     }
 
     fn get_many_leq(
@@ -142,12 +129,11 @@ impl KVQBinaryStoreReader for KVQRocksDBStore {
         keys: &[Vec<u8>],
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
-        let mut results: Vec<Option<Vec<u8>>> = Vec::with_capacity(keys.len());
-        for k in keys {
-            let r = self.get_leq(k, fuzzy_bytes)?;
-            results.push(r);
-        }
-        Ok(results)
+        Ok(self
+            .get_many_leq_kv(keys, fuzzy_bytes)?
+            .into_iter()
+            .map(|r| r.map(|kv| kv.value))
+            .collect())
     }
 
     fn get_many_leq_kv(
@@ -155,10 +141,17 @@ impl KVQBinaryStoreReader for KVQRocksDBStore {
         keys: &[Vec<u8>],
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>>> {
-        let mut results: Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>> = Vec::with_capacity(keys.len());
-        for k in keys {
-            let r = self.get_leq_kv(k, fuzzy_bytes)?;
-            results.push(r);
+        let base_keys = keys
+            .iter()
+            .map(|k| get_fuzzy_base_key(k, fuzzy_bytes))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // one iterator, and so one view of the db, for the whole batch, seeking the keys in ascending order
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+        let mut iter = self.db.raw_iterator();
+        let mut results = vec![None; keys.len()];
+        for i in order {
+            results[i] = seek_leq(&mut iter, &base_keys[i], &keys[i])?;
         }
         Ok(results)
     }
@@ -177,32 +170,94 @@ impl KVQBinaryStoreReader for KVQRocksDBStore {
         key: &Vec<u8>,
         fuzzy_bytes: usize,
     ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
-        let key_end = key.to_vec();
-        let mut base_key = key.to_vec();
-        let key_len = base_key.len();
-        if fuzzy_bytes > key_len {
-            return Err(anyhow::anyhow!(
-                "Fuzzy bytes must be less than or equal to key length"
-            ));
+        let base_key = get_fuzzy_base_key(key, fuzzy_bytes)?;
+        let mut iter = self
+            .db
+            .raw_iterator_opt(fuzzy_range_read_options(&base_key, key));
+        iter.seek(&base_key);
+        let mut result = Vec::new();
+        while let (Some(k), Some(v)) = (iter.key(), iter.value()) {
+            result.push(KVQPair {
+                key: k.to_vec(),
+                value: v.to_vec(),
+            });
+            iter.next();
         }
+        iter.status()?;
+        Ok(result)
+    }
 
-        for i in 0..fuzzy_bytes {
-            base_key[key_len - i - 1] = 0;
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        let (lower, upper) = match get_range_scan_bounds(start, end, start_after, false) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
+        let seek_key = match &lower {
+            Bound::Included(k) | Bound::Excluded(k) => k.to_vec(),
+            Bound::Unbounded => Vec::new(),
+        };
+        let mut result = Vec::new();
+        for item in self
+            .db
+            .iterator(IteratorMode::From(&seek_key, Direction::Forward))
+        {
+            if result.len() >= limit {
+                break;
+            }
+            let (k, v) = item?;
+            if !is_above_lower_bound(&k, &lower) {
+                continue;
+            }
+            if !is_below_upper_bound(&k, &upper) {
+                break;
+            }
+            result.push(KVQPair {
+                key: k.to_vec(),
+                value: v.to_vec(),
+            });
         }
-        self.db
-            .prefix_iterator(base_key)
-            .take_while(|v| match v {
-                Ok((k, _)) if compare_u8_array_a_le_b(k.as_ref(), &key_end) => true,
-                _ => false,
-            })
-            .map(|x| {
-                let x = x?;
-                Ok(KVQPair {
-                    key: x.0.to_vec(),
-                    value: x.1.to_vec(),
-                })
-            })
-            .collect::<Result<Vec<_>, _>>()
+        Ok(result)
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        let (lower, upper) = match get_range_scan_bounds(start, end, start_after, true) {
+            Some(b) => b,
+            None => return Ok(Vec::new()),
+        };
+        let mode = match &upper {
+            Bound::Included(k) | Bound::Excluded(k) => IteratorMode::From(k, Direction::Reverse),
+            Bound::Unbounded => IteratorMode::End,
+        };
+        let mut result = Vec::new();
+        for item in self.db.iterator(mode) {
+            if result.len() >= limit {
+                break;
+            }
+            let (k, v) = item?;
+            if !is_below_upper_bound(&k, &upper) {
+                continue;
+            }
+            if !is_above_lower_bound(&k, &lower) {
+                break;
+            }
+            result.push(KVQPair {
+                key: k.to_vec(),
+                value: v.to_vec(),
+            });
+        }
+        Ok(result)
     }
 }
 /*
@@ -341,6 +396,19 @@ impl KVQBinaryStoreWriterImmutable for KVQRocksDBStore {
         self.db.flush()?;
         Ok(())
     }
+
+    fn imm_write_batch(&self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        let mut write_batch = rocksdb::WriteBatch::default();
+        for op in batch.ops {
+            match op {
+                KVQWriteOp::Set { key, value } => write_batch.put(key, value),
+                KVQWriteOp::Delete { key } => write_batch.delete(key),
+            }
+        }
+        self.db.write(write_batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
 }
 
 impl KVQBinaryStoreWriterAutoImmutable for KVQRocksDBStore {}
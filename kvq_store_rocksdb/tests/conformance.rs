@@ -0,0 +1,13 @@
+use kvq_store_rocksdb::KVQRocksDBStore;
+
+#[test]
+fn rocksdb_store_conforms() {
+    let path = std::env::temp_dir().join(format!("kvq_rocksdb_conformance_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    let result = {
+        let mut store = KVQRocksDBStore::open_default(&path).unwrap();
+        kvq::testing::check_store(&mut store)
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    result.unwrap();
+}
@@ -1,8 +1,7 @@
-use bitcoin::Address;
-use std::{path, str::FromStr, sync::Arc};
+use std::{path, sync::Arc};
 use txindex_common::{
     api::{response::TxIndexAPIResponse, traits::TxIndexAPIHandler},
-    chain::Network,
+    chain::{address_to_script, Network},
     db::{
         chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStoreReader, kvstore::BaseKVQStore,
     },
@@ -11,7 +10,7 @@ use txindex_common::{
 
 use crate::{
     tables::SimpleTxCounterDB,
-    utils::get_scriptpubkey_hash_for_address,
+    utils::get_scriptpubkey_hash,
 };
 
 pub struct TxCounterAPI<T: TxIndexChainAPI> {
@@ -30,9 +29,9 @@ impl<T: TxIndexChainAPI> TxCounterAPI<T> {
         match (path.get(0), path.get(1), path.get(2)) {
             (Some(&"address"), address, Some(&"stats")) => {
                 let address_str = address.ok_or_else(|| anyhow::anyhow!("missing address"))?;
-                let p = Address::from_str(address_str)?.require_network(network.into())?;
+                let script = address_to_script(address_str, network)?;
 
-                let sh = get_scriptpubkey_hash_for_address(&p);
+                let sh = get_scriptpubkey_hash(&script);
 
                 let db = indexer_db
                     .get::<SimpleTxCounterDB>(&sh)?
@@ -1,4 +1,5 @@
 pub mod tables;
 pub mod worker;
 pub mod api;
+pub mod module;
 mod utils;
\ No newline at end of file
@@ -0,0 +1,26 @@
+use std::marker::PhantomData;
+
+use kvq::traits::KVQBinaryStoreImmutable;
+use txindex_common::{
+    db::{chain::TxIndexChainAPI, table::core::KVQTableInfo},
+    module::traits::TxIndexModule,
+};
+
+use crate::{api::TxCounterAPI, tables::SimpleTxCounterDB, worker::TxCounterWorker};
+
+pub struct TxCounterModule<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> {
+    pub _kvq: PhantomData<KVQ>,
+    pub _chain: PhantomData<T>,
+}
+impl<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> TxIndexModule<KVQ, T>
+    for TxCounterModule<KVQ, T>
+{
+    const MODULE_NAME: &'static str = "tx_counter";
+
+    type Worker = TxCounterWorker<KVQ, T>;
+    type API = TxCounterAPI<T>;
+
+    fn tables() -> Vec<KVQTableInfo> {
+        vec![KVQTableInfo::of::<SimpleTxCounterDB>()]
+    }
+}
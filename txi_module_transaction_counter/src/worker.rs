@@ -1,10 +1,10 @@
 use std::{marker::PhantomData, sync::Arc};
 
-use bitcoin::{Block, Transaction};
+use bitcoin::{Address, ScriptBuf};
 use itertools::Itertools;
 use kvq::{cache::KVQBinaryStoreCached, traits::KVQBinaryStoreImmutable};
 use txindex_common::{
-    db::{chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStore}, utils::transaction::{get_input_addresses_for_transaction, get_output_addresses_for_transaction}, worker::traits::TxIndexWorker
+    db::{chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStore}, utils::transaction::get_output_addresses_for_transaction, worker::{context::TxIndexTransactionContext, mode::TxWorkerProcessingMode, traits::TxIndexWorker}
 };
 
 use crate::{tables::SimpleTxCounterDB, utils::get_scriptpubkey_hash};
@@ -15,59 +15,51 @@ pub struct TxCounterWorker<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> {
     pub _chain: PhantomData<T>,
 }
 impl<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> TxCounterWorker<KVQ, T> {
-    fn process_tx(
+    fn add_to_counter(
         db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
-        q: Arc<T>,
-        _block_number: u64,
-        _block: &Block,
-        tx: &Transaction,
+        script_pubkey: &ScriptBuf,
+        spend_count: u64,
+        receive_count: u64,
     ) -> anyhow::Result<()> {
-        let inputs = get_input_addresses_for_transaction::<T>(&q, tx).into_iter().unique().collect::<Vec<_>>();
-        let outputs = get_output_addresses_for_transaction(tx, q.get_network()).into_iter().unique().collect::<Vec<_>>();
-        for input in inputs.iter() {
-            let hash = get_scriptpubkey_hash(&input.script_pubkey());
-            let ctr = db
-                .get::<SimpleTxCounterDB>(&hash)?
-                .or(Some(SimpleTxCounterDB { spend_count: 0, receive_count: 0 }))
-                .unwrap();
-            db.put::<SimpleTxCounterDB>(
-                &hash,
-                &SimpleTxCounterDB {
-                    spend_count: ctr.spend_count + 1,
-                    receive_count: ctr.receive_count,
-                },
-            )?;
-        }
-        for output in outputs.iter() {
-            let hash = get_scriptpubkey_hash(&output.script_pubkey());
-            let ctr = db
-                .get::<SimpleTxCounterDB>(&hash)?
-                .or(Some(SimpleTxCounterDB { spend_count: 0, receive_count: 0 }))
-                .unwrap();
-            db.put::<SimpleTxCounterDB>(
-                &hash,
-                &SimpleTxCounterDB {
-                    spend_count: ctr.spend_count,
-                    receive_count: ctr.receive_count + 1,
-                },
-            )?;
-        }
-        Ok(())
+        let hash = get_scriptpubkey_hash(script_pubkey);
+        let ctr = db
+            .get::<SimpleTxCounterDB>(&hash)?
+            .or(Some(SimpleTxCounterDB { spend_count: 0, receive_count: 0 }))
+            .unwrap();
+        db.put::<SimpleTxCounterDB>(
+            &hash,
+            &SimpleTxCounterDB {
+                spend_count: ctr.spend_count + spend_count,
+                receive_count: ctr.receive_count + receive_count,
+            },
+        )
     }
 }
 impl<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> TxIndexWorker<KVQ, T>
     for TxCounterWorker<KVQ, T>
 {
-    fn process_block(
+    const PROCESSING_MODE: TxWorkerProcessingMode = TxWorkerProcessingMode::TransactionsWithBlockNumberSuffix;
+
+    fn process_transaction(
         db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
         q: Arc<T>,
-        block_number: u64,
-        block: &Block,
+        ctx: &TxIndexTransactionContext,
     ) -> anyhow::Result<()> {
-        for tx in block.txdata.iter() {
-            Self::process_tx(db, q.clone(), block_number, block, tx)?;
+        let bitcoin_network = q.get_bitcoin_network();
+        let inputs = ctx
+            .prevouts
+            .iter()
+            .flatten()
+            .filter_map(|prevout| Address::from_script(&prevout.script_pubkey, bitcoin_network).ok())
+            .unique()
+            .collect::<Vec<_>>();
+        let outputs = get_output_addresses_for_transaction(ctx.tx, q.get_network()).into_iter().unique().collect::<Vec<_>>();
+        for input in inputs.iter() {
+            Self::add_to_counter(db, &input.script_pubkey(), 1, 0)?;
+        }
+        for output in outputs.iter() {
+            Self::add_to_counter(db, &output.script_pubkey(), 0, 1)?;
         }
-
         Ok(())
     }
 }
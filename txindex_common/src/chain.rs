@@ -1,6 +1,9 @@
+use bitcoin::base58;
 use bitcoin::hashes::Hash;
 pub use bitcoin::network::Network as BNetwork;
+use bitcoin::{PubkeyHash, ScriptHash};
 use serde::Serialize;
+use std::str::FromStr;
 
 pub use bitcoin::{
     address, blockdata::block::Header as BlockHeader, blockdata::script, consensus::deserialize,
@@ -14,8 +17,14 @@ pub enum Network {
     Testnet,
     Regtest,
     Signet,
+    Dogecoin,
+    DogecoinTestnet,
+    DogecoinRegtest,
 }
 
+/// Block version bit set on merge-mined (AuxPoW) Dogecoin blocks.
+pub const AUXPOW_VERSION_FLAG: i32 = 1 << 8;
+
 
 impl From<&String> for Network {
     fn from(network_name: &String) -> Self {
@@ -25,7 +34,10 @@ impl From<&String> for Network {
             "testnet" => Network::Testnet,
             "regtest" => Network::Regtest,
             "signet" => Network::Signet,
-            _ => panic!("unsupported Bitcoin network: {:?}", network_name),
+            "dogecoin" => Network::Dogecoin,
+            "dogecoin-testnet" => Network::DogecoinTestnet,
+            "dogecoin-regtest" => Network::DogecoinRegtest,
+            _ => panic!("unsupported network: {:?}", network_name),
         }
     }
   }
@@ -37,11 +49,17 @@ impl From<&str> for Network {
           "testnet" => Network::Testnet,
           "regtest" => Network::Regtest,
           "signet" => Network::Signet,
-          _ => panic!("unsupported Bitcoin network: {:?}", network_name),
+          "dogecoin" => Network::Dogecoin,
+          "dogecoin-testnet" => Network::DogecoinTestnet,
+          "dogecoin-regtest" => Network::DogecoinRegtest,
+          _ => panic!("unsupported network: {:?}", network_name),
       }
   }
 }
 
+/// Maps dogecoin networks onto the bitcoin network of the same role. Bitcoin base58 prefixes,
+/// magic and genesis hashes don't apply to them: dogecoin addresses go through
+/// [`address_to_script`] and [`dogecoin_script_to_address`].
 impl From<Network> for BNetwork {
   fn from(network: Network) -> Self {
       match network {
@@ -49,6 +67,10 @@ impl From<Network> for BNetwork {
           Network::Testnet => BNetwork::Testnet,
           Network::Regtest => BNetwork::Regtest,
           Network::Signet => BNetwork::Signet,
+          // rust-dogecoin reuses the bitcoin network names for the dogecoin chain params
+          Network::Dogecoin => BNetwork::Bitcoin,
+          Network::DogecoinTestnet => BNetwork::Testnet,
+          Network::DogecoinRegtest => BNetwork::Regtest,
       }
   }
 }
@@ -61,29 +83,70 @@ pub type Value = u64;
 
 impl Network {
   pub fn magic(self) -> u32 {
-      u32::from_le_bytes(BNetwork::from(self).magic().to_bytes())
+      match self {
+          Network::Dogecoin => u32::from_le_bytes([0xc0, 0xc0, 0xc0, 0xc0]),
+          Network::DogecoinTestnet => u32::from_le_bytes([0xfc, 0xc1, 0xb7, 0xdc]),
+          Network::DogecoinRegtest => u32::from_le_bytes([0xfa, 0xbf, 0xb5, 0xda]),
+          _ => u32::from_le_bytes(BNetwork::from(self).magic().to_bytes()),
+      }
   }
 
   pub fn is_regtest(self) -> bool {
       match self {
-          Network::Regtest => true,
+          Network::Regtest | Network::DogecoinRegtest => true,
           _ => false,
       }
   }
 
+  pub fn is_dogecoin(self) -> bool {
+      match self {
+          Network::Dogecoin | Network::DogecoinTestnet | Network::DogecoinRegtest => true,
+          _ => false,
+      }
+  }
+
+  /// Whether blocks on this network may carry a merge-mining (AuxPoW) header.
+  pub fn has_auxpow(self) -> bool {
+      self.is_dogecoin()
+  }
+
+  /// Base58 version bytes for (p2pkh, p2sh) addresses on dogecoin networks.
+  pub fn dogecoin_address_prefixes(self) -> Option<(u8, u8)> {
+      match self {
+          Network::Dogecoin => Some((0x1e, 0x16)),
+          Network::DogecoinTestnet => Some((0x71, 0xc4)),
+          Network::DogecoinRegtest => Some((0x6f, 0xc4)),
+          _ => None,
+      }
+  }
+
   pub fn names() -> Vec<String> {
       return vec![
           "mainnet".to_string(),
           "testnet".to_string(),
           "regtest".to_string(),
           "signet".to_string(),
+          "dogecoin".to_string(),
+          "dogecoin-testnet".to_string(),
+          "dogecoin-regtest".to_string(),
       ];
   }
 }
 
 
 pub fn genesis_hash(network: Network) -> BlockHash {
-    return bitcoin_genesis_hash(network.into());
+    match network {
+        Network::Dogecoin => BlockHash::from_byte_array(hex_literal::hex!(
+            "9156352c1818b32e90c9e792efd6a11a82fe7956a630f03bbee236cedae3911a"
+        )),
+        Network::DogecoinTestnet => BlockHash::from_byte_array(hex_literal::hex!(
+            "9e555073d0c4f36456db8951f449704d544d2826d9aa60636b40374626780abb"
+        )),
+        Network::DogecoinRegtest => BlockHash::from_byte_array(hex_literal::hex!(
+            "a573e91c1772076c0d40f70e4408c83a31705f296ae6e7629d4adcb5a360213d"
+        )),
+        _ => bitcoin_genesis_hash(network.into()),
+    }
 }
 
 pub fn bitcoin_genesis_hash(network: BNetwork) -> bitcoin::BlockHash {
@@ -102,4 +165,91 @@ pub fn bitcoin_genesis_hash(network: BNetwork) -> bitcoin::BlockHash {
         )),
         _ => panic!("unknown network {:?}", network),
     }
-}
\ No newline at end of file
+}
+
+/// Decodes an address of the given network into its output script.
+pub fn address_to_script(addr: &str, network: Network) -> anyhow::Result<Script> {
+    if network.is_dogecoin() {
+        return dogecoin_address_to_script(addr, network);
+    }
+    Ok(Address::from_str(addr)?.require_network(network.into())?.script_pubkey())
+}
+
+/// Decodes a base58 dogecoin address into its output script.
+pub fn dogecoin_address_to_script(addr: &str, network: Network) -> anyhow::Result<Script> {
+    let (p2pkh_prefix, p2sh_prefix) = network
+        .dogecoin_address_prefixes()
+        .ok_or_else(|| anyhow::anyhow!("{:?} is not a dogecoin network", network))?;
+    let data = base58::decode_check(addr)?;
+    if data.len() != 21 {
+        anyhow::bail!("invalid dogecoin address length");
+    }
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&data[1..]);
+    if data[0] == p2pkh_prefix {
+        Ok(Script::new_p2pkh(&PubkeyHash::from_byte_array(hash)))
+    } else if data[0] == p2sh_prefix {
+        Ok(Script::new_p2sh(&ScriptHash::from_byte_array(hash)))
+    } else {
+        anyhow::bail!("Address on invalid network")
+    }
+}
+
+/// Encodes a p2pkh/p2sh output script as a base58 dogecoin address.
+pub fn dogecoin_script_to_address(script: &bitcoin::Script, network: Network) -> Option<String> {
+    let (p2pkh_prefix, p2sh_prefix) = network.dogecoin_address_prefixes()?;
+    let bytes = script.as_bytes();
+    let (prefix, hash) = if script.is_p2pkh() {
+        (p2pkh_prefix, &bytes[3..23])
+    } else if script.is_p2sh() {
+        (p2sh_prefix, &bytes[2..22])
+    } else {
+        return None;
+    };
+    let mut data = Vec::with_capacity(21);
+    data.push(prefix);
+    data.extend_from_slice(hash);
+    Some(base58::encode_check(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dogecoin_address_prefixes() {
+        assert_eq!(Network::Dogecoin.dogecoin_address_prefixes(), Some((0x1e, 0x16)));
+        assert_eq!(Network::DogecoinTestnet.dogecoin_address_prefixes(), Some((0x71, 0xc4)));
+        assert_eq!(Network::DogecoinRegtest.dogecoin_address_prefixes(), Some((0x6f, 0xc4)));
+        assert_eq!(Network::Bitcoin.dogecoin_address_prefixes(), None);
+    }
+
+    #[test]
+    fn dogecoin_address_roundtrip() {
+        let p2pkh = Script::new_p2pkh(&PubkeyHash::from_byte_array([7; 20]));
+        let p2sh = Script::new_p2sh(&ScriptHash::from_byte_array([9; 20]));
+        for network in [Network::Dogecoin, Network::DogecoinTestnet, Network::DogecoinRegtest] {
+            for script in [&p2pkh, &p2sh] {
+                let addr = dogecoin_script_to_address(script, network).unwrap();
+                assert_eq!(&address_to_script(&addr, network).unwrap(), script);
+            }
+        }
+        // testnet and regtest share the p2sh prefix but not the p2pkh one
+        let regtest = dogecoin_script_to_address(&p2pkh, Network::DogecoinRegtest).unwrap();
+        let testnet = dogecoin_script_to_address(&p2pkh, Network::DogecoinTestnet).unwrap();
+        assert!(address_to_script(&regtest, Network::DogecoinTestnet).is_err());
+        assert!(address_to_script(&testnet, Network::DogecoinRegtest).is_err());
+    }
+
+    #[test]
+    fn address_to_script_checks_network() {
+        let script = Script::new_p2pkh(&PubkeyHash::from_byte_array([7; 20]));
+        let doge = dogecoin_script_to_address(&script, Network::Dogecoin).unwrap();
+        assert!(doge.starts_with('D'));
+        assert!(address_to_script(&doge, Network::Bitcoin).is_err());
+
+        let btc = Address::from_script(&script, BNetwork::Bitcoin).unwrap().to_string();
+        assert_eq!(address_to_script(&btc, Network::Bitcoin).unwrap(), script);
+        assert!(address_to_script(&btc, Network::Dogecoin).is_err());
+    }
+}
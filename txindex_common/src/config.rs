@@ -12,6 +12,7 @@ use stderrlog;
 
 use crate::daemon::cookie::CookieGetter;
 use crate::chain::Network;
+use crate::db::kvstore::TxIndexKVQBackend;
 use txindex_errors::core::*;
 
 
@@ -28,6 +29,7 @@ pub struct Config {
     pub log: stderrlog::StdErrLog,
     pub network_type: Network,
     pub db_path: PathBuf,
+    pub indexer_backend: TxIndexKVQBackend,
     pub daemon_dir: PathBuf,
     pub blocks_dir: PathBuf,
     pub daemon_rpc_url: Url,
@@ -35,12 +37,17 @@ pub struct Config {
     pub http_addr: SocketAddr,
     pub http_socket_file: Option<PathBuf>,
     pub monitoring_addr: SocketAddr,
+    pub admin_addr: Option<SocketAddr>,
+    pub admin_token: Option<String>,
     pub jsonrpc_import: bool,
     pub light_mode: bool,
     pub address_search: bool,
     pub index_unspendables: bool,
     pub cors: Option<String>,
     pub precache_scripts: Option<String>,
+    pub verify_db: bool,
+    pub undo_horizon: Option<u64>,
+    pub full_history_tables: Vec<u32>,
     pub utxos_limit: usize,
     pub electrum_txs_limit: usize,
     pub electrum_banner: String,
@@ -56,6 +63,20 @@ fn str_to_socketaddr(address: &str, what: &str) -> SocketAddr {
         .unwrap()
 }
 
+fn str_to_table_ids(table_ids: &str) -> std::result::Result<Vec<u32>, String> {
+    table_ids
+        .split(',')
+        .map(|id| {
+            let id = id.trim();
+            match id.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => id.parse::<u32>(),
+            }
+            .map_err(|_| format!("invalid table id {:?}, expected a decimal or 0x prefixed hex number", id))
+        })
+        .collect()
+}
+
 fn str_to_url(address: &str, what: &str) -> Url {
     Url::from_str(address).unwrap_or_else(|_| panic!("unable to resolve {} url", what))
 }
@@ -63,6 +84,10 @@ fn str_to_url(address: &str, what: &str) -> Url {
 impl Config {
     pub fn from_args() -> Config {
         let network_help = format!("Select network type ({})", Network::names().join(", "));
+        let indexer_backend_help = format!(
+            "Select the indexer db backend ({}, default: rocksdb)",
+            TxIndexKVQBackend::names().join(", ")
+        );
         let rpc_logging_help = format!(
             "Select RPC logging option ({})",
             RpcLogging::options().join(", ")
@@ -86,10 +111,15 @@ impl Config {
                     .long("db-dir")
                     .help("Directory to store index database (default: ./db/)"),
             )
+            .arg(
+                Arg::new("indexer_backend")
+                    .long("indexer-backend")
+                    .help(indexer_backend_help),
+            )
             .arg(
                 Arg::new("daemon_dir")
                     .long("daemon-dir")
-                    .help("Data directory of Bitcoind (default: ~/.bitcoin/)"),
+                    .help("Data directory of Bitcoind (default: ~/.bitcoin/, or ~/.dogecoin/ for dogecoin networks)"),
             )
             .arg(
                 Arg::new("blocks_dir")
@@ -115,17 +145,27 @@ impl Config {
             .arg(
                 Arg::new("http_addr")
                     .long("http-addr")
-                    .help("HTTP server 'addr:port' to listen on (default: '127.0.0.1:3000' for mainnet, '127.0.0.1:3001' for testnet and '127.0.0.1:3002' for regtest)"),
+                    .help("HTTP server 'addr:port' to listen on (default: '127.0.0.1:3000' for mainnet, '127.0.0.1:3001' for testnet, '127.0.0.1:3002' for regtest and '127.0.0.1:3010' for dogecoin)"),
             )
             .arg(
                 Arg::new("daemon_rpc_url")
                     .long("daemon-rpc-url")
-                    .help("Bitcoin daemon JSONRPC 'http://addr:port/path' to connect (default: http://127.0.0.1:8332 for mainnet, http://127.0.0.1:18332 for testnet and http://127.0.0.1:18443 for regtest)"),
+                    .help("Bitcoin daemon JSONRPC 'http://addr:port/path' to connect (default: http://127.0.0.1:8332 for mainnet, http://127.0.0.1:18332 for testnet, http://127.0.0.1:18443 for regtest and http://127.0.0.1:22555 for dogecoin)"),
             )
             .arg(
                 Arg::new("monitoring_addr")
                     .long("monitoring-addr")
-                    .help("Prometheus monitoring 'addr:port' to listen on (default: 127.0.0.1:4224 for mainnet, 127.0.0.1:14224 for testnet and 127.0.0.1:24224 for regtest)"),
+                    .help("Prometheus monitoring 'addr:port' to listen on (default: 127.0.0.1:4224 for mainnet, 127.0.0.1:14224 for testnet, 127.0.0.1:24224 for regtest and 127.0.0.1:4234 for dogecoin)"),
+            )
+            .arg(
+                Arg::new("admin_addr")
+                    .long("admin-addr")
+                    .help("Admin API address to listen on (default: disabled). Requires --admin-token")
+            )
+            .arg(
+                Arg::new("admin_token")
+                    .long("admin-token")
+                    .help("Token admin API requests must send as 'Authorization: Bearer <token>'")
             )
             .arg(
                 Arg::new("jsonrpc_import")
@@ -158,6 +198,24 @@ impl Config {
                     .long("precache-scripts")
                     .help("Path to file with list of scripts to pre-cache")
             )
+            .arg(
+                Arg::new("verify_db")
+                    .long("verify-db")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Check that every undo record of the indexer db decodes and references consistent keys, then exit")
+            )
+            .arg(
+                Arg::new("undo_horizon")
+                    .long("undo-horizon")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .help("Number of blocks that can be rolled back. Older undo records and superseded versions of fuzzy block indexed keys are pruned (default: keep everything)")
+            )
+            .arg(
+                Arg::new("full_history_tables")
+                    .long("full-history-tables")
+                    .value_parser(str_to_table_ids)
+                    .help("Comma separated ids of the fuzzy block indexed tables whose old versions are kept when pruning, e.g. 0x100,0x101")
+            )
             .arg(
                 Arg::new("utxos_limit")
                     .long("utxos-limit")
@@ -200,18 +258,27 @@ impl Config {
             Network::Testnet => 18332,
             Network::Regtest => 18443,
             Network::Signet => 38332,
+            Network::Dogecoin => 22555,
+            Network::DogecoinTestnet => 44555,
+            Network::DogecoinRegtest => 18332,
         };
         let default_http_port = match network_type {
             Network::Bitcoin => 3000,
             Network::Testnet => 3001,
             Network::Regtest => 3002,
             Network::Signet => 3003,
+            Network::Dogecoin => 3010,
+            Network::DogecoinTestnet => 3011,
+            Network::DogecoinRegtest => 3012,
         };
         let default_monitoring_port = match network_type {
             Network::Bitcoin => 4224,
             Network::Testnet => 14224,
             Network::Regtest => 24224,
             Network::Signet => 54224,
+            Network::Dogecoin => 4234,
+            Network::DogecoinTestnet => 14234,
+            Network::DogecoinRegtest => 24234,
         };
 
         let daemon_rpc_url: Url = str_to_url(
@@ -234,7 +301,7 @@ impl Config {
             .map(PathBuf::from)
             .unwrap_or_else(|| {
                 let mut default_dir = home_dir().expect("no homedir");
-                default_dir.push(".bitcoin");
+                default_dir.push(if network_type.is_dogecoin() { ".dogecoin" } else { ".bitcoin" });
                 default_dir
             });
 
@@ -264,6 +331,7 @@ impl Config {
             log,
             network_type,
             db_path,
+            indexer_backend: TxIndexKVQBackend::from(get_or_default_str(&m, "indexer_backend", "rocksdb").as_str()),
             daemon_dir,
             blocks_dir,
             daemon_rpc_url,
@@ -277,12 +345,17 @@ impl Config {
             http_addr,
             http_socket_file,
             monitoring_addr,
+            admin_addr: m.get_one::<String>("admin_addr").map(|addr| str_to_socketaddr(addr, "Admin API")),
+            admin_token: m.get_one::<String>("admin_token").map(|s| s.to_string()),
             jsonrpc_import: m.contains_id("jsonrpc_import"),
             light_mode: m.contains_id("light_mode"),
             address_search: m.contains_id("address_search"),
             index_unspendables: m.contains_id("index_unspendables"),
             cors: m.get_one::<String>("cors").map(|s| s.to_string()),
             precache_scripts: m.get_one::<String>("precache_scripts").map(|s| s.to_string()),
+            verify_db: m.get_flag("verify_db"),
+            undo_horizon: m.get_one::<u64>("undo_horizon").copied(),
+            full_history_tables: m.get_one::<Vec<u32>>("full_history_tables").cloned().unwrap_or_default(),
 
         };
         eprintln!("{:?}", config);
@@ -331,6 +404,9 @@ pub fn get_network_subdir(network: Network) -> Option<&'static str> {
         Network::Testnet => Some("testnet3"),
         Network::Regtest => Some("regtest"),
         Network::Signet => Some("signet"),
+        Network::Dogecoin => None,
+        Network::DogecoinTestnet => Some("testnet3"),
+        Network::DogecoinRegtest => Some("regtest"),
     }
 }
 
@@ -357,3 +433,15 @@ impl CookieGetter for CookieFile {
         Ok(contents)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::str_to_table_ids;
+
+    #[test]
+    fn table_ids_parse_decimal_and_hex() {
+        assert_eq!(str_to_table_ids("0x100, 7"), Ok(vec![0x100, 7]));
+        assert!(str_to_table_ids("0x100,0x1g").unwrap_err().contains("0x1g"));
+        assert!(str_to_table_ids("").is_err());
+    }
+}
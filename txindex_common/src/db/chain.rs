@@ -2,7 +2,7 @@ use bitcoin::Transaction;
 
 use crate::chain::Network;
 
-pub trait TxIndexChainAPI {
+pub trait TxIndexChainAPI: Send + Sync {
   fn get_transaction(&self, txid: [u8; 32]) -> anyhow::Result<Transaction>;
   fn get_block(&self, block_number: u64) -> anyhow::Result<bitcoin::Block>;
   fn get_blockhash(&self, block_number: u64) -> anyhow::Result<bitcoin::BlockHash>;
@@ -0,0 +1,185 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use kvq::cache::KVQBinaryStoreCachedTrait;
+use serde::{Deserialize, Serialize};
+
+use super::{indexed_block::SerializedIndexedBlockAction, indexed_block_db::IndexedBlockDBStore, table::{core::{KVQTable, TABLE_TYPE_WRITE_ONCE}, traits::{deserialize_raw_key_for_table, get_table_key_prefix}}};
+
+/// Actions emitted by workers, keyed by block number, worker id and action index (all big endian).
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedBlockActionsByBlock;
+
+impl KVQTable for IndexedBlockActionsByBlock {
+  type Key = [u8; 16];
+  type Value = SerializedIndexedBlockAction;
+  const TABLE_TYPE: u8 = TABLE_TYPE_WRITE_ONCE;
+
+  const TABLE_NAME: &'static str = "indexed_block_action";
+
+  const TABLE_ID: u32 = 2;
+}
+
+/// Actions emitted by workers, keyed by txid, block number, worker id and action index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedBlockActionsByTxid;
+
+impl KVQTable for IndexedBlockActionsByTxid {
+  type Key = [u8; 48];
+  type Value = SerializedIndexedBlockAction;
+  const TABLE_TYPE: u8 = TABLE_TYPE_WRITE_ONCE;
+
+  const TABLE_NAME: &'static str = "indexed_block_action_by_txid";
+
+  const TABLE_ID: u32 = 3;
+}
+
+/// Actions emitted by workers, keyed by worker id, block number and action index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedBlockActionsByWorker;
+
+impl KVQTable for IndexedBlockActionsByWorker {
+  type Key = [u8; 16];
+  type Value = SerializedIndexedBlockAction;
+  const TABLE_TYPE: u8 = TABLE_TYPE_WRITE_ONCE;
+
+  const TABLE_NAME: &'static str = "indexed_block_action_by_worker";
+
+  const TABLE_ID: u32 = 4;
+}
+
+/// Position of an action: a worker's actions in a block are numbered from 0 in the order they were emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct IndexedBlockActionId {
+  pub block_number: u64,
+  pub worker_id: u32,
+  pub action_index: u32,
+}
+
+impl IndexedBlockActionId {
+  pub fn new(block_number: u64, worker_id: u32, action_index: u32) -> Self {
+    Self {
+      block_number,
+      worker_id,
+      action_index,
+    }
+  }
+  pub fn to_block_key(&self) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[0..8].copy_from_slice(&self.block_number.to_be_bytes());
+    key[8..12].copy_from_slice(&self.worker_id.to_be_bytes());
+    key[12..16].copy_from_slice(&self.action_index.to_be_bytes());
+    key
+  }
+  pub fn from_block_key(key: &[u8; 16]) -> Self {
+    Self {
+      block_number: u64::from_be_bytes(key[0..8].try_into().unwrap()),
+      worker_id: u32::from_be_bytes(key[8..12].try_into().unwrap()),
+      action_index: u32::from_be_bytes(key[12..16].try_into().unwrap()),
+    }
+  }
+  pub fn to_txid_key(&self, txid: &[u8; 32]) -> [u8; 48] {
+    let mut key = [0u8; 48];
+    key[0..32].copy_from_slice(txid);
+    key[32..48].copy_from_slice(&self.to_block_key());
+    key
+  }
+  pub fn from_txid_key(key: &[u8; 48]) -> Self {
+    Self::from_block_key(key[32..48].try_into().unwrap())
+  }
+  pub fn to_worker_key(&self) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[0..4].copy_from_slice(&self.worker_id.to_be_bytes());
+    key[4..12].copy_from_slice(&self.block_number.to_be_bytes());
+    key[12..16].copy_from_slice(&self.action_index.to_be_bytes());
+    key
+  }
+  pub fn from_worker_key(key: &[u8; 16]) -> Self {
+    Self {
+      worker_id: u32::from_be_bytes(key[0..4].try_into().unwrap()),
+      block_number: u64::from_be_bytes(key[4..12].try_into().unwrap()),
+      action_index: u32::from_be_bytes(key[12..16].try_into().unwrap()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedBlockAction {
+  pub id: IndexedBlockActionId,
+  pub action: SerializedIndexedBlockAction,
+}
+
+// Index of the next action of `worker_id` at `block_number`, following the actions already saved (e.g. by the other
+// modules of a block a module is catching up on).
+fn get_next_action_index<S: KVQBinaryStoreCachedTrait>(db: &IndexedBlockDBStore<S>, block_number: u64, worker_id: u32) -> anyhow::Result<u32> {
+  let mut key_prefix = block_number.to_be_bytes().to_vec();
+  key_prefix.extend_from_slice(&worker_id.to_be_bytes());
+  let prefix = get_table_key_prefix::<IndexedBlockActionsByBlock>(&key_prefix);
+  match db.store.get_prefix_kv_reverse(&prefix, None, 1)?.first() {
+    Some(kv) => {
+      let key = deserialize_raw_key_for_table::<IndexedBlockActionsByBlock>(&kv.key)?;
+      Ok(IndexedBlockActionId::from_block_key(&key).action_index + 1)
+    }
+    None => Ok(0),
+  }
+}
+
+/// Writes the actions emitted in `db` (each at the block number it was emitted at) to the action tables and returns
+/// them with their ids. A worker's actions in a block are numbered after the ones already saved, and the rows are
+/// written through the write-once `put`, so an action never replaces another one.
+pub fn write_indexed_block_actions<S: KVQBinaryStoreCachedTrait>(db: &mut IndexedBlockDBStore<S>) -> anyhow::Result<Vec<IndexedBlockAction>> {
+  let actions = std::mem::take(&mut db.actions);
+  let mut counters: HashMap<(u64, u32), u32> = HashMap::new();
+  let mut written = Vec::with_capacity(actions.len());
+  for pair in actions.iter() {
+    let counter = match counters.entry((pair.key, pair.value.worker_id)) {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => entry.insert(get_next_action_index(db, pair.key, pair.value.worker_id)?),
+    };
+    let id = IndexedBlockActionId::new(pair.key, pair.value.worker_id, *counter);
+    *counter += 1;
+
+    db.put::<IndexedBlockActionsByBlock>(&id.to_block_key(), &pair.value)?;
+    db.put::<IndexedBlockActionsByTxid>(&id.to_txid_key(&pair.value.txid), &pair.value)?;
+    db.put::<IndexedBlockActionsByWorker>(&id.to_worker_key(), &pair.value)?;
+    written.push(IndexedBlockAction { id, action: pair.value.clone() });
+  }
+  db.actions = actions;
+  Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use kvq::cache::KVQBinaryStoreCached;
+
+  use super::*;
+  use crate::{db::{indexed_block::IndexedBlockFull, indexed_block_db::IndexedBlockDBStoreReader, table::{core::KVQTableWrapper, traits::KVQTableReaderAtBlock}}, testing::*};
+
+  fn emit(store: &Arc<TestStore>, q: &TestChain, worker_id: u32, action_type: u32) -> IndexedBlockDBStore<KVQBinaryStoreCached<TestStore>> {
+    let block = &q.blocks[1];
+    let mut db = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(store)), 1, block);
+    db.emit_action_from_txid(&block.txdata[0].txid(), worker_id, action_type, &0u64).unwrap();
+    db
+  }
+
+  #[test]
+  fn action_indexes_follow_the_saved_actions() {
+    let q = TestChain::new(2);
+    let store = new_store();
+    let mut db = emit(&store, &q, 7, 0);
+    db.emit_action_from_txid(&q.blocks[1].txdata[0].txid(), 8, 1, &0u64).unwrap();
+    IndexedBlockFull::save_from_db_store(db).unwrap();
+
+    // amending the block with more actions of worker 7 numbers them after the saved one
+    let record = KVQTableWrapper::<IndexedBlockFull, TestStore>::get_exact_at_block(store.as_ref(), 1, &1).unwrap();
+    let actions = record.amend_from_db_store(emit(&store, &q, 7, 2)).unwrap();
+    assert_eq!(actions[0].id, IndexedBlockActionId::new(1, 7, 1));
+
+    let reader = IndexedBlockDBStoreReader::new(Arc::clone(&store), None);
+    let ids = reader.get_actions_by_block(1, None, 10).unwrap().into_iter().map(|a| (a.id, a.action.action_type)).collect::<Vec<_>>();
+    assert_eq!(ids, vec![(IndexedBlockActionId::new(1, 7, 0), 0), (IndexedBlockActionId::new(1, 7, 1), 2), (IndexedBlockActionId::new(1, 8, 0), 1)]);
+    rollback_to(&store, 1).unwrap();
+    assert!(dump(&store).is_empty());
+  }
+}
@@ -1,11 +1,13 @@
+use std::collections::HashSet;
+
 use bitcoin::{Block, Txid};
-use kvq::{cache::{CacheValueType, KVQBinaryStoreCached, KVQBinaryStoreCachedTrait}, traits::{KVQBinaryStoreReader, KVQSerializable}};
+
+use kvq::{cache::{CacheValueType, KVQBinaryStoreCached}, traits::{KVQBinaryStoreImmutable, KVQBinaryStoreReader, KVQSerializable, KVQWriteBatch}};
 use serde::{Deserialize, Serialize};
 
 
-use super::{indexed_block_db::IndexedBlockDBStore, kvstore::BaseKVQStore, table::{core::{KVQTable, TABLE_TYPE_FUZZY_BLOCK_INDEX, TABLE_TYPE_STANDARD, TABLE_TYPE_WRITE_ONCE}, traits::{get_real_key_at_block, get_table_type_for_raw_key}}};
+use super::{indexed_action::{write_indexed_block_actions, IndexedBlockAction}, indexed_block_db::IndexedBlockDBStore, table::{core::{KVQTable, KVQTableWrapper, TABLE_TYPE_FUZZY_BLOCK_INDEX, TABLE_TYPE_STANDARD, TABLE_TYPE_WRITE_ONCE}, traits::{get_real_key_at_block, get_table_key_prefix, get_table_type_for_raw_key, KVQTableReaderAtBlock}}};
 
-use kvq::traits::KVQBinaryStoreWriterImmutable;
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct IndexedBlockMetadata {
@@ -130,30 +132,71 @@ impl IndexedBlockFull {
     });
   }
   
-  pub fn save_from_db_store(db_store: IndexedBlockDBStore<KVQBinaryStoreCached<BaseKVQStore>>) -> anyhow::Result<()> {
-    
-    let mut indexed_block = IndexedBlockFull::new(db_store.metadata.clone());
+  /// Saves the block's changes and its `IndexedBlockFull` record, returning the actions emitted while indexing it.
+  pub fn save_from_db_store<S: KVQBinaryStoreImmutable>(db_store: IndexedBlockDBStore<KVQBinaryStoreCached<S>>) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    IndexedBlockFull::new(db_store.metadata.clone()).commit_from_db_store(db_store)
+  }
+  /// Saves changes made at the already indexed block of this record, or at the blocks indexed with it, (e.g. by a module
+  /// catching up) and adds them to the record, so rolling the block back also undoes them. Fails if a change touches a
+  /// key the record already lists.
+  pub fn amend_from_db_store<S: KVQBinaryStoreImmutable>(self, db_store: IndexedBlockDBStore<KVQBinaryStoreCached<S>>) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    if db_store.block_number != self.metadata.block_number {
+      anyhow::bail!("cannot amend the record of block {} with changes made at block {}", self.metadata.block_number, db_store.block_number);
+    }
+    self.commit_from_db_store(db_store)
+  }
+  /// Saves changes made at a block whose undo record was pruned, without recording them.
+  pub fn save_unrecorded_from_db_store<S: KVQBinaryStoreImmutable>(mut db_store: IndexedBlockDBStore<KVQBinaryStoreCached<S>>) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    let actions = write_indexed_block_actions(&mut db_store)?;
+    db_store.store.store.imm_write_batch(db_store.store.to_write_batch())?;
+    Ok(actions)
+  }
+  fn commit_from_db_store<S: KVQBinaryStoreImmutable>(mut self, mut db_store: IndexedBlockDBStore<KVQBinaryStoreCached<S>>) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    // the action index rows are write-once keys of this block, so rolling it back removes them too
+    let actions = write_indexed_block_actions(&mut db_store)?;
+    self.add_changes(&db_store.store)?;
+    self.actions.extend(db_store.actions.into_iter().map(|kv| kv.value));
+    // the block's changes and its undo record are committed in one batch
+    let mut batch = db_store.store.to_write_batch();
+    let key = get_real_key_at_block::<IndexedBlockFull>(&self.metadata.block_number, self.metadata.block_number)?;
+    batch.set(key, self.to_bytes()?);
+    db_store.store.store.imm_write_batch(batch)?;
 
-    for (key, vt) in db_store.store.map.iter() {
+    Ok(actions)
+  }
+  // Lists the writes cached in `store`, reading the saved values of the standard keys they replace.
+  fn add_changes<S: KVQBinaryStoreImmutable>(&mut self, store: &KVQBinaryStoreCached<S>) -> anyhow::Result<()> {
+    let listed: HashSet<Vec<u8>> = self.added_fuzzy_block_keys.iter()
+      .chain(self.added_write_once_keys.iter())
+      .chain(self.removed_standard_keys.iter().map(|x| &x.key))
+      .chain(self.modified_standard_keys.iter().map(|x| &x.key))
+      .chain(self.added_standard_keys.iter().map(|x| &x.key))
+      .cloned()
+      .collect();
+    for (key, vt) in store.map.iter() {
+      if listed.contains(key) {
+        anyhow::bail!("block {}: key {} is already listed in the undo record", self.metadata.block_number, hex::encode(key));
+      }
       let key_type = get_table_type_for_raw_key(&key);
       match vt {
         CacheValueType::Bytes(new_value) => {
           match key_type {
             TABLE_TYPE_WRITE_ONCE => {
-              indexed_block.added_write_once_keys.push(key.to_vec());
+              self.added_write_once_keys.push(key.to_vec());
             },
             TABLE_TYPE_FUZZY_BLOCK_INDEX => {
-              indexed_block.added_fuzzy_block_keys.push(key.to_vec());
+              self.added_fuzzy_block_keys.push(key.to_vec());
             },
             TABLE_TYPE_STANDARD => {
-              let old_value = db_store.store.get_exact_if_exists(key)?;
+              // read the saved value, the cache only has the new one
+              let old_value = store.store.get_exact_if_exists(key)?;
               if old_value.is_none() {
-                indexed_block.added_standard_keys.push(SerializedAddedStandardKey{
+                self.added_standard_keys.push(SerializedAddedStandardKey{
                   key: key.to_vec(),
                   new_value: new_value.to_vec(),
                 });
               }else{
-                indexed_block.modified_standard_keys.push(SerializedModifiedStandardKey{
+                self.modified_standard_keys.push(SerializedModifiedStandardKey{
                   key: key.to_vec(),
                   new_value: new_value.to_vec(),
                   old_value: old_value.unwrap(),
@@ -167,9 +210,9 @@ impl IndexedBlockFull {
         CacheValueType::Removed => {
           match key_type {
             TABLE_TYPE_STANDARD => {
-              let old_value = db_store.store.get_exact_if_exists(key)?;
+              let old_value = store.store.get_exact_if_exists(key)?;
               if old_value.is_some() {
-                indexed_block.removed_standard_keys.push(SerializedRemovedStandardKey{
+                self.removed_standard_keys.push(SerializedRemovedStandardKey{
                   key: key.to_vec(),
                   value: old_value.unwrap(),
                 });
@@ -181,21 +224,118 @@ impl IndexedBlockFull {
       }
 
     }
-    indexed_block.actions.extend_from_slice(&db_store.actions);
-    let mut db_store = db_store.store;
-    db_store.flush_simple()?;
-
-
-
-
-    let key = get_real_key_at_block::<IndexedBlockFull>(&indexed_block.metadata.block_number,indexed_block.metadata.block_number)?;
-    db_store.store.imm_set(key, indexed_block.to_bytes()?)?;
-
     Ok(())
+  }
+  /// Returns the block number of the undo record stored under the raw `key`.
+  pub fn get_block_number_from_key(key: &[u8]) -> anyhow::Result<u64> {
+    if key.len() != 12 {
+      anyhow::bail!("undo record key {} is invalid", hex::encode(key));
+    }
+    Ok(u64::from_be_bytes(key[4..12].try_into()?))
+  }
+  /// Returns the block number of the latest undo record, `None` if no block was indexed.
+  pub fn get_latest_block_number<S: KVQBinaryStoreReader>(store: &S) -> anyhow::Result<Option<u64>> {
+    Ok(KVQTableWrapper::<IndexedBlockFull, S>::get_leq_kv_at_block(store, 0x1fffffffffffffff, &0x1fffffffffffffffu64, 8)?.map(|r| r.key))
+  }
+  /// Returns the batch that undoes this block: it restores the standard keys the block modified or removed, then
+  /// deletes the keys it added and its own record.
+  pub fn get_undo_batch(&self) -> anyhow::Result<KVQWriteBatch> {
+    let mut batch = KVQWriteBatch::with_capacity(self.modified_standard_keys.len() + self.removed_standard_keys.len() + self.added_fuzzy_block_keys.len() + self.added_write_once_keys.len() + self.added_standard_keys.len() + 1);
+    self.modified_standard_keys.iter().for_each(|x| batch.set(x.key.clone(), x.old_value.clone()));
+    self.removed_standard_keys.iter().for_each(|x| batch.set(x.key.clone(), x.value.clone()));
+    self.added_fuzzy_block_keys.iter().chain(self.added_write_once_keys.iter()).chain(self.added_standard_keys.iter().map(|x| &x.key)).for_each(|key| batch.delete(key.clone()));
+    batch.delete(get_real_key_at_block::<IndexedBlockFull>(&self.metadata.block_number, self.metadata.block_number)?);
+    Ok(batch)
+  }
+}
 
-  
+/// Result of `IndexedBlockFull::verify_all`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexedBlockVerification {
+  /// Number of undo records found.
+  pub records: usize,
+  /// One message per problem found, empty if the records are consistent.
+  pub errors: Vec<String>,
+}
 
-    
+impl IndexedBlockFull {
+  /// Checks that this record, saved as the undo record of `block_number`, only references keys it could have written:
+  /// keys of the listed table type, fuzzy keys versioned after the `previous_block_number` record and no later than
+  /// the block (blocks indexed without a record of their own are saved with the next block, and modules catching up
+  /// add their changes to the same record), no key listed twice, and added fuzzy and write-once keys still present in
+  /// `store` (neither can be removed without rolling the block back). Returns a message per problem found.
+  pub fn check_consistency<S: KVQBinaryStoreReader>(&self, store: &S, block_number: u64, previous_block_number: Option<u64>) -> anyhow::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    if self.metadata.block_number != block_number {
+      errors.push(format!("block {}: record is for block {}", block_number, self.metadata.block_number));
+    }
+    let mut seen: HashSet<&[u8]> = HashSet::new();
+    let lists: [(&str, u8, Vec<&Vec<u8>>); 5] = [
+      ("added fuzzy", TABLE_TYPE_FUZZY_BLOCK_INDEX, self.added_fuzzy_block_keys.iter().collect()),
+      ("added write-once", TABLE_TYPE_WRITE_ONCE, self.added_write_once_keys.iter().collect()),
+      ("removed standard", TABLE_TYPE_STANDARD, self.removed_standard_keys.iter().map(|x| &x.key).collect()),
+      ("modified standard", TABLE_TYPE_STANDARD, self.modified_standard_keys.iter().map(|x| &x.key).collect()),
+      ("added standard", TABLE_TYPE_STANDARD, self.added_standard_keys.iter().map(|x| &x.key).collect()),
+    ];
+    for (name, table_type, keys) in lists.iter() {
+      for key in keys {
+        let min_len = if *table_type == TABLE_TYPE_FUZZY_BLOCK_INDEX { 12 } else { 4 };
+        if key.len() < min_len {
+          errors.push(format!("block {}: {} key {} is too short", block_number, name, hex::encode(key)));
+          continue;
+        }
+        if get_table_type_for_raw_key(key) != *table_type {
+          errors.push(format!("block {}: {} key {} has table type {}", block_number, name, hex::encode(key), get_table_type_for_raw_key(key)));
+        }
+        if !seen.insert(key.as_slice()) {
+          errors.push(format!("block {}: key {} is listed more than once", block_number, hex::encode(key)));
+        }
+        if *table_type == TABLE_TYPE_FUZZY_BLOCK_INDEX {
+          let version = u64::from_be_bytes(key[key.len() - 8..].try_into()?);
+          if version > block_number || previous_block_number.map_or(false, |previous| version <= previous) {
+            errors.push(format!("block {}: {} key {} is versioned at block {}", block_number, name, hex::encode(key), version));
+          }
+        }
+        if *table_type != TABLE_TYPE_STANDARD && store.get_exact_if_exists(key)?.is_none() {
+          errors.push(format!("block {}: {} key {} is missing from the store", block_number, name, hex::encode(key)));
+        }
+      }
+    }
+    Ok(errors)
+  }
+
+  /// Decodes every undo record in `store`, oldest first, and checks each one with `check_consistency`.
+  pub fn verify_all<S: KVQBinaryStoreReader>(store: &S) -> anyhow::Result<IndexedBlockVerification> {
+    const PAGE_SIZE: usize = 256;
+    let prefix = get_table_key_prefix::<IndexedBlockFull>(&[]);
+    let mut verification = IndexedBlockVerification::default();
+    let mut previous_block_number: Option<u64> = None;
+    let mut cursor: Option<Vec<u8>> = None;
+    loop {
+      let page = store.get_prefix_kv(&prefix, cursor.as_ref(), PAGE_SIZE)?;
+      let is_last_page = page.len() < PAGE_SIZE;
+      for kv in page {
+        verification.records += 1;
+        let block_number = match IndexedBlockFull::get_block_number_from_key(&kv.key) {
+          Ok(block_number) => block_number,
+          Err(err) => {
+            verification.errors.push(err.to_string());
+            cursor = Some(kv.key);
+            continue;
+          }
+        };
+        match IndexedBlockFull::from_bytes(&kv.value) {
+          Ok(record) => verification.errors.extend(record.check_consistency(store, block_number, previous_block_number)?),
+          Err(err) => verification.errors.push(format!("block {}: undo record does not decode: {}", block_number, err)),
+        }
+        previous_block_number = Some(block_number);
+        cursor = Some(kv.key);
+      }
+      if is_last_page {
+        break;
+      }
+    }
+    Ok(verification)
   }
 }
 
@@ -225,3 +365,102 @@ impl KVQSerializable for SerializedIndexedBlockAction {
     Ok(bincode::deserialize(bytes)?)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use kvq::testing::{KVQTestRng, CONFORMANCE_SEEDS};
+
+  use super::*;
+  use crate::{db::table::core::KVQWriteOnceViolation, testing::*};
+
+  const BLOCKS: u64 = 24;
+
+  #[derive(Clone, Copy, Debug)]
+  enum TestOp {
+    Put(u32, u64),
+    Delete(u32),
+    PutOnce(u32, u64),
+    PutFuzzy(u32, u64),
+    DeleteFuzzy(u32),
+    Emit(u32),
+  }
+
+  // few keys and values, so blocks overwrite, delete and re-put each other's keys
+  fn random_ops(rng: &mut KVQTestRng) -> Vec<TestOp> {
+    (0..rng.below(6))
+      .map(|_| {
+        let key = rng.below(4) as u32;
+        let value = rng.below(3) as u64;
+        match rng.below(6) {
+          0 => TestOp::Put(key, value),
+          1 => TestOp::Delete(key),
+          2 => TestOp::PutOnce(key, value),
+          3 => TestOp::PutFuzzy(key, value),
+          4 => TestOp::DeleteFuzzy(key),
+          _ => TestOp::Emit(key),
+        }
+      })
+      .collect()
+  }
+
+  fn apply_block(store: &Arc<TestStore>, q: &TestChain, block_number: u64, ops: &[TestOp]) -> anyhow::Result<()> {
+    let block = &q.blocks[block_number as usize];
+    let mut db = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(store)), block_number, block);
+    for op in ops {
+      match *op {
+        TestOp::Put(key, value) => db.put::<TestCounters<0>>(&key, &value)?,
+        TestOp::Delete(key) => {
+          db.delete::<TestCounters<0>>(&key)?;
+        }
+        TestOp::PutOnce(key, value) => {
+          if let Err(err) = db.put::<TestRecords<0>>(&key, &value) {
+            err.downcast::<KVQWriteOnceViolation>()?;
+          }
+        }
+        TestOp::PutFuzzy(key, value) => db.put::<TestHistory<0>>(&key, &value)?,
+        TestOp::DeleteFuzzy(key) => {
+          db.delete::<TestHistory<0>>(&key)?;
+        }
+        TestOp::Emit(action_type) => db.emit_action_from_txid(&block.txdata[0].txid(), 0, action_type, &block_number)?,
+      }
+    }
+    IndexedBlockFull::save_from_db_store(db)?;
+    Ok(())
+  }
+
+  fn replay(q: &TestChain, blocks: &[Vec<TestOp>]) -> Arc<TestStore> {
+    let store = new_store();
+    for (block_number, ops) in blocks.iter().enumerate() {
+      apply_block(&store, q, block_number as u64, ops).unwrap();
+    }
+    store
+  }
+
+  #[test]
+  fn rollbacks_match_a_fresh_store_indexed_to_the_same_block() {
+    let q = TestChain::new(BLOCKS as usize);
+    for seed in CONFORMANCE_SEEDS {
+      let mut rng = KVQTestRng::new(seed);
+      let store = new_store();
+      let mut blocks: Vec<Vec<TestOp>> = Vec::new();
+      let mut rollbacks = 0;
+      while (blocks.len() as u64) < BLOCKS {
+        let ops = random_ops(&mut rng);
+        apply_block(&store, &q, blocks.len() as u64, &ops).unwrap();
+        blocks.push(ops);
+        if rng.below(4) == 0 {
+          let rollback_to_block = blocks.len() - 1 - rng.below(blocks.len().min(4));
+          rollback_to(&store, rollback_to_block as u64).unwrap();
+          blocks.truncate(rollback_to_block);
+          rollbacks += 1;
+          assert_eq!(dump(&store), dump(&replay(&q, &blocks)), "seed {:#x}, rollback {} to block {}", seed, rollbacks, rollback_to_block);
+        }
+      }
+      assert_eq!(dump(&store), dump(&replay(&q, &blocks)), "seed {:#x}", seed);
+      rollback_to(&store, 0).unwrap();
+      assert!(dump(&store).is_empty(), "seed {:#x}", seed);
+    }
+  }
+}
@@ -1,17 +1,18 @@
-use std::{borrow::BorrowMut, sync::Arc};
+use std::{borrow::BorrowMut, collections::HashMap, sync::Arc};
 
-use bitcoin::Block;
-use kvq::{cache::KVQBinaryStoreCachedTrait, traits::{KVQBinaryStoreReader, KVQPair}};
+use bitcoin::{Block, Txid};
+use kvq::{cache::{KVQBinaryStoreCached, KVQBinaryStoreCachedTrait}, traits::{KVQBinaryStoreReader, KVQPair, KVQSerializable}};
 
 
-use super::{indexed_block::{IndexedBlockFull, IndexedBlockMetadata, SerializedIndexedBlockAction}, table::{core::{KVQTable, KVQTableWrapper, TABLE_TYPE_FUZZY_BLOCK_INDEX}, traits::{KVQTableReaderAtBlock, KVQTableWriterAtBlock}}};
+use super::{indexed_action::{IndexedBlockAction, IndexedBlockActionId, IndexedBlockActionsByBlock, IndexedBlockActionsByTxid, IndexedBlockActionsByWorker}, indexed_block::{IndexedBlockFull, IndexedBlockMetadata, SerializedIndexedBlockAction}, table::{core::{KVQTable, KVQTableWrapper, KVQWriteOnceViolation, TABLE_TYPE_FUZZY_BLOCK_INDEX, TABLE_TYPE_WRITE_ONCE}, traits::{KVQTableReaderAtBlock, KVQTableWriterAtBlock}}};
 
 #[derive(Debug, Clone)]
 pub struct IndexedBlockDBStore<S: KVQBinaryStoreCachedTrait> {
   pub store: S,
   pub block_number: u64,
   pub metadata: IndexedBlockMetadata,
-  pub actions: Vec<SerializedIndexedBlockAction>,
+  /// Actions emitted so far, with the block number they were emitted at.
+  pub actions: Vec<KVQPair<u64, SerializedIndexedBlockAction>>,
 }
 
 impl<S: KVQBinaryStoreCachedTrait> IndexedBlockDBStore<S> {
@@ -38,14 +39,91 @@ impl<S: KVQBinaryStoreCachedTrait> IndexedBlockDBStore<S> {
       KVQTableWrapper::<T, S>::get_exact_if_exists_at_block(&self.store, self.block_number, key)
     }
   }
+  // Returns false if a write-once `key` already holds `value`, and a `KVQWriteOnceViolation` if it holds another value.
+  fn should_write_once<T: KVQTable>(&self, key: &T::Key, value: &T::Value) -> anyhow::Result<bool> {
+    match KVQTableWrapper::<T, S>::get_exact_if_exists_at_block(&self.store, self.block_number, key)? {
+      None => Ok(true),
+      Some(old) if old == *value => Ok(false),
+      Some(_) => Err(KVQWriteOnceViolation::new::<T>(key)?.into()),
+    }
+  }
+  /// Writes `value` at the current block. Writing an existing key of a write-once table is a no-op if the value is
+  /// unchanged and fails with a `KVQWriteOnceViolation` otherwise.
   pub fn put<T: KVQTable>(&mut self, key: &T::Key, value: &T::Value) -> anyhow::Result<()> {
+    if T::TABLE_TYPE == TABLE_TYPE_WRITE_ONCE && !self.should_write_once::<T>(key, value)? {
+      return Ok(());
+    }
     KVQTableWrapper::<T, S>::set_ref_at_block(self.store.borrow_mut(), self.block_number, key, value)
   }
+  /// Like `put`; nothing is written if any item violates a write-once table.
   pub fn put_many_ref<T: KVQTable>(&mut self, items: &[KVQPair<&T::Key, &T::Value>]) -> anyhow::Result<()> {
-    KVQTableWrapper::<T, S>::set_many_ref_at_block(self.store.borrow_mut(), self.block_number, items)
+    if T::TABLE_TYPE != TABLE_TYPE_WRITE_ONCE {
+      return KVQTableWrapper::<T, S>::set_many_ref_at_block(self.store.borrow_mut(), self.block_number, items);
+    }
+    let mut written: HashMap<Vec<u8>, &T::Value> = HashMap::new();
+    let mut to_write = Vec::with_capacity(items.len());
+    for item in items {
+      let key_bytes = item.key.to_bytes()?;
+      let write = match written.get(&key_bytes) {
+        Some(value) if *value == item.value => false,
+        Some(_) => return Err(KVQWriteOnceViolation::new::<T>(item.key)?.into()),
+        None => self.should_write_once::<T>(item.key, item.value)?,
+      };
+      if write {
+        written.insert(key_bytes, item.value);
+        to_write.push(KVQPair { key: item.key, value: item.value });
+      }
+    }
+    KVQTableWrapper::<T, S>::set_many_ref_at_block(self.store.borrow_mut(), self.block_number, &to_write)
   }
   pub fn put_many<T: KVQTable>(&mut self, items: &[KVQPair<T::Key, T::Value>]) -> anyhow::Result<()> {
-    KVQTableWrapper::<T, S>::set_many_at_block(self.store.borrow_mut(), self.block_number, items)
+    let items = items.iter().map(|item| KVQPair { key: &item.key, value: &item.value }).collect::<Vec<_>>();
+    self.put_many_ref::<T>(&items)
+  }
+  /// Deletes `key` at the current block and returns whether it existed; the deletion is undone if the block is rolled
+  /// back. A fuzzy block indexed key reads as absent from the current block on, while older blocks still see its
+  /// previous value. Keys of write-once tables cannot be deleted.
+  pub fn delete<T: KVQTable>(&mut self, key: &T::Key) -> anyhow::Result<bool> {
+    if T::TABLE_TYPE == TABLE_TYPE_WRITE_ONCE {
+      anyhow::bail!("cannot delete a key of write-once table {}", T::TABLE_NAME);
+    }
+    if self.get::<T>(key)?.is_none() {
+      return Ok(false);
+    }
+    if T::TABLE_TYPE == TABLE_TYPE_FUZZY_BLOCK_INDEX {
+      KVQTableWrapper::<T, S>::set_tombstone_at_block(self.store.borrow_mut(), self.block_number, key)?;
+    }else{
+      KVQTableWrapper::<T, S>::delete_at_block(self.store.borrow_mut(), self.block_number, key)?;
+    }
+    Ok(true)
+  }
+  pub fn delete_many<T: KVQTable>(&mut self, keys: &[T::Key]) -> anyhow::Result<Vec<bool>> {
+    keys.iter().map(|key| self.delete::<T>(key)).collect()
+  }
+  pub fn scan<T: KVQTable>(&self, key_prefix: &[u8], start_after: Option<&T::Key>, limit: usize) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+    KVQTableWrapper::<T, S>::scan_prefix_at_block(&self.store, self.block_number, key_prefix, start_after, limit)
+  }
+  pub fn scan_range<T: KVQTable>(&self, start: Option<&T::Key>, end: Option<&T::Key>, start_after: Option<&T::Key>, limit: usize) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+    KVQTableWrapper::<T, S>::scan_range_at_block(&self.store, self.block_number, start, end, start_after, limit)
+  }
+  /// Records an action for the current block; actions are saved with the block and served by the actions API.
+  pub fn emit_action(&mut self, action: SerializedIndexedBlockAction) {
+    self.actions.push(KVQPair {
+      key: self.block_number,
+      value: action,
+    });
+  }
+  pub fn emit_action_from_txid<P: KVQSerializable>(&mut self, txid: &Txid, worker_id: u32, action_type: u32, action_data: &P) -> anyhow::Result<()> {
+    let mut txid_bytes = [0u8; 32];
+    txid_bytes.copy_from_slice(txid.as_ref());
+
+    self.emit_action(SerializedIndexedBlockAction {
+      txid: txid_bytes,
+      worker_id,
+      action_type,
+      action_data: action_data.to_bytes()?,
+    });
+    Ok(())
   }
   pub fn get_latest_synced_block(&self) -> anyhow::Result<u64> {
     let r = KVQTableWrapper::<IndexedBlockFull, S>::get_leq_kv_at_block(&self.store, 0x1fffffffffffffff, &0x1fffffffffffffffu64, 0)?;
@@ -58,15 +136,34 @@ impl<S: KVQBinaryStoreCachedTrait> IndexedBlockDBStore<S> {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct IndexedBlockDBStoreReader<S: KVQBinaryStoreReader> {
   pub store: Arc<S>,
+  /// The confirmed state with the mempool transactions applied, if the server runs a `TxIndexMempoolWorker`.
+  pub pending: Option<Arc<KVQBinaryStoreCached<S>>>,
 }
 
 impl<S: KVQBinaryStoreReader> IndexedBlockDBStoreReader<S> {
+  pub fn new(store: Arc<S>, pending: Option<Arc<KVQBinaryStoreCached<S>>>) -> Self {
+    Self {
+      store,
+      pending,
+    }
+  }
   pub fn new_from_block(store: S) -> Self {
     Self {
       store: Arc::new(store),
+      pending: None,
+    }
+  }
+  /// Returns a reader whose queries include the pending (mempool) state, or only the confirmed state if there is none.
+  pub fn with_pending(&self) -> IndexedBlockDBStoreReader<KVQBinaryStoreCached<S>> {
+    IndexedBlockDBStoreReader {
+      store: match &self.pending {
+        Some(pending) => Arc::clone(pending),
+        None => Arc::new(KVQBinaryStoreCached::new(Arc::clone(&self.store))),
+      },
+      pending: None,
     }
   }
 
@@ -77,4 +174,165 @@ impl<S: KVQBinaryStoreReader> IndexedBlockDBStoreReader<S> {
       KVQTableWrapper::<T, S>::get_exact_if_exists_at_block(&self.store, 0xffffffffffffffff, key)
     }
   }
-}
\ No newline at end of file
+  pub fn scan<T: KVQTable>(&self, key_prefix: &[u8], start_after: Option<&T::Key>, limit: usize) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+    KVQTableWrapper::<T, S>::scan_prefix_at_block(&self.store, 0xffffffffffffffff, key_prefix, start_after, limit)
+  }
+  pub fn scan_range<T: KVQTable>(&self, start: Option<&T::Key>, end: Option<&T::Key>, start_after: Option<&T::Key>, limit: usize) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+    KVQTableWrapper::<T, S>::scan_range_at_block(&self.store, 0xffffffffffffffff, start, end, start_after, limit)
+  }
+  /// Returns the value of `key` as of `block_number`. Only fuzzy block indexed tables keep past values.
+  pub fn get_at_block<T: KVQTable>(&self, key: &T::Key, block_number: u64) -> anyhow::Result<Option<T::Value>> {
+    if T::TABLE_TYPE != TABLE_TYPE_FUZZY_BLOCK_INDEX {
+      anyhow::bail!("table {} does not keep past values", T::TABLE_NAME);
+    }
+    KVQTableWrapper::<T, S>::get_leq_at_block(&self.store, block_number, key, 0)
+  }
+  /// Returns every version of `key` written between `from_block` and `to_block` (inclusive) with its block number;
+  /// `None` marks a deletion.
+  pub fn history<T: KVQTable>(&self, key: &T::Key, from_block: u64, to_block: u64) -> anyhow::Result<Vec<KVQPair<u64, Option<T::Value>>>> {
+    KVQTableWrapper::<T, S>::get_history_at_blocks(&self.store, key, from_block, to_block)
+  }
+  /// Returns the actions emitted at `block_number`, ordered by worker id and emission order.
+  pub fn get_actions_by_block(&self, block_number: u64, start_after: Option<&IndexedBlockActionId>, limit: usize) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    let start_after = start_after.map(|id| id.to_block_key());
+    Ok(
+      self
+        .scan::<IndexedBlockActionsByBlock>(&block_number.to_be_bytes(), start_after.as_ref(), limit)?
+        .into_iter()
+        .map(|kv| IndexedBlockAction { id: IndexedBlockActionId::from_block_key(&kv.key), action: kv.value })
+        .collect(),
+    )
+  }
+  /// Returns the actions emitted for `txid` (in internal byte order), oldest first.
+  pub fn get_actions_by_txid(&self, txid: &[u8; 32], start_after: Option<&IndexedBlockActionId>, limit: usize) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    let start_after = start_after.map(|id| id.to_txid_key(txid));
+    Ok(
+      self
+        .scan::<IndexedBlockActionsByTxid>(txid, start_after.as_ref(), limit)?
+        .into_iter()
+        .map(|kv| IndexedBlockAction { id: IndexedBlockActionId::from_txid_key(&kv.key), action: kv.value })
+        .collect(),
+    )
+  }
+  /// Returns the actions emitted by `worker_id`, oldest first.
+  pub fn get_actions_by_worker(&self, worker_id: u32, start_after: Option<&IndexedBlockActionId>, limit: usize) -> anyhow::Result<Vec<IndexedBlockAction>> {
+    let start_after = start_after.map(|id| id.to_worker_key());
+    Ok(
+      self
+        .scan::<IndexedBlockActionsByWorker>(&worker_id.to_be_bytes(), start_after.as_ref(), limit)?
+        .into_iter()
+        .map(|kv| IndexedBlockAction { id: IndexedBlockActionId::from_worker_key(&kv.key), action: kv.value })
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use kvq::memory::simple::KVQSimpleMemoryBackingStore;
+
+  use super::*;
+  use crate::{db::table::core::KVQTableInfo, module::cursor::TxIndexModuleSyncCursor, testing::*};
+
+  #[derive(Clone, Debug, PartialEq)]
+  struct TestFuzzyTable;
+
+  impl KVQTable for TestFuzzyTable {
+    type Key = u32;
+    type Value = u64;
+    const TABLE_TYPE: u8 = TABLE_TYPE_FUZZY_BLOCK_INDEX;
+    const TABLE_NAME: &'static str = "test_fuzzy";
+    const TABLE_ID: u32 = 0x1234;
+  }
+
+  #[test]
+  fn reader_get_at_block_reads_past_versions() {
+    let mut store = KVQSimpleMemoryBackingStore::new();
+    KVQTableWrapper::<TestFuzzyTable, _>::set_ref_at_block(&mut store, 3, &7, &30).unwrap();
+    KVQTableWrapper::<TestFuzzyTable, _>::set_ref_at_block(&mut store, 5, &7, &50).unwrap();
+    let reader = IndexedBlockDBStoreReader::new_from_block(store);
+    assert_eq!(reader.get_at_block::<TestFuzzyTable>(&7, 2).unwrap(), None);
+    assert_eq!(reader.get_at_block::<TestFuzzyTable>(&7, 4).unwrap(), Some(30));
+    assert_eq!(reader.get_at_block::<TestFuzzyTable>(&7, 5).unwrap(), Some(50));
+  }
+
+  #[test]
+  fn reader_get_at_block_rejects_tables_without_versions() {
+    let mut store = KVQSimpleMemoryBackingStore::new();
+    KVQTableWrapper::<TxIndexModuleSyncCursor, _>::set_ref_at_block(&mut store, 3, &1, &3).unwrap();
+    let reader = IndexedBlockDBStoreReader::new_from_block(store);
+    assert!(reader.get_at_block::<TxIndexModuleSyncCursor>(&1, 3).is_err());
+    assert_eq!(reader.get::<TxIndexModuleSyncCursor>(&1).unwrap(), Some(3));
+  }
+
+  #[derive(Clone, Debug, PartialEq)]
+  struct TestWriteOnceTable;
+
+  impl KVQTable for TestWriteOnceTable {
+    type Key = u32;
+    type Value = u64;
+    const TABLE_TYPE: u8 = TABLE_TYPE_WRITE_ONCE;
+    const TABLE_NAME: &'static str = "test_write_once";
+    const TABLE_ID: u32 = 0x1235;
+  }
+
+  fn new_db(store: &Arc<TestStore>, q: &TestChain, block_number: u64) -> IndexedBlockDBStore<KVQBinaryStoreCached<TestStore>> {
+    IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(store)), block_number, &q.blocks[block_number as usize])
+  }
+
+  fn get_violation(err: anyhow::Error) -> KVQWriteOnceViolation {
+    err.downcast::<KVQWriteOnceViolation>().expect("a write-once violation")
+  }
+
+  #[test]
+  fn write_once_reput_of_the_same_value_is_a_noop() {
+    let q = TestChain::new(2);
+    let store = new_store();
+    let mut db = new_db(&store, &q, 0);
+    db.put::<TestWriteOnceTable>(&1, &10).unwrap();
+    db.put::<TestWriteOnceTable>(&1, &10).unwrap();
+    IndexedBlockFull::save_from_db_store(db).unwrap();
+
+    let mut db = new_db(&store, &q, 1);
+    db.put::<TestWriteOnceTable>(&1, &10).unwrap();
+    db.put_many::<TestWriteOnceTable>(&[KVQPair { key: 1, value: 10 }]).unwrap();
+    assert!(db.store.map.is_empty());
+    IndexedBlockFull::save_from_db_store(db).unwrap();
+    // the key is not listed in the record of block 1, so rolling it back keeps it
+    rollback_to(&store, 1).unwrap();
+    assert_eq!(new_db(&store, &q, 1).get::<TestWriteOnceTable>(&1).unwrap(), Some(10));
+  }
+
+  #[test]
+  fn write_once_conflicting_put_is_a_typed_violation() {
+    let q = TestChain::new(2);
+    let store = new_store();
+    let mut db = new_db(&store, &q, 0);
+    db.put::<TestWriteOnceTable>(&1, &10).unwrap();
+    // conflicts are caught against the block's own pending writes and against saved values
+    assert_eq!(get_violation(db.put::<TestWriteOnceTable>(&1, &11).unwrap_err()), KVQWriteOnceViolation::new::<TestWriteOnceTable>(&1).unwrap());
+    IndexedBlockFull::save_from_db_store(db).unwrap();
+
+    let mut db = new_db(&store, &q, 1);
+    let violation = get_violation(db.put::<TestWriteOnceTable>(&1, &11).unwrap_err());
+    assert_eq!(violation.table, KVQTableInfo::of::<TestWriteOnceTable>());
+    assert_eq!(violation.key, 1u32.to_bytes().unwrap());
+    assert!(db.store.map.is_empty());
+    assert_eq!(db.get::<TestWriteOnceTable>(&1).unwrap(), Some(10));
+  }
+
+  #[test]
+  fn write_once_conflicting_duplicate_in_a_batch_fails() {
+    let q = TestChain::new(1);
+    let store = new_store();
+    let mut db = new_db(&store, &q, 0);
+    db.put_many::<TestWriteOnceTable>(&[KVQPair { key: 1, value: 10 }, KVQPair { key: 1, value: 10 }]).unwrap();
+
+    let err = db.put_many::<TestWriteOnceTable>(&[KVQPair { key: 2, value: 20 }, KVQPair { key: 3, value: 30 }, KVQPair { key: 2, value: 21 }]).unwrap_err();
+    assert_eq!(get_violation(err), KVQWriteOnceViolation::new::<TestWriteOnceTable>(&2).unwrap());
+    // nothing of the failed batch is written
+    assert_eq!(db.get::<TestWriteOnceTable>(&2).unwrap(), None);
+    assert_eq!(db.get::<TestWriteOnceTable>(&3).unwrap(), None);
+    assert_eq!(db.get::<TestWriteOnceTable>(&1).unwrap(), Some(10));
+  }
+}
@@ -1,11 +1,159 @@
-use std::{collections::HashSet, sync::{Arc, RwLock}};
+use std::{collections::HashSet, path::Path, sync::{Arc, RwLock}};
 
 use bitcoin::BlockHash;
+use kvq::{memory::{immutable::KVQImmutableStoreWrapper, simple::KVQSimpleMemoryBackingStore}, traits::{KVQBinaryStoreImmutable, KVQBinaryStoreReader, KVQBinaryStoreWriterAutoImmutable, KVQBinaryStoreWriterImmutable, KVQPair, KVQWriteBatch}};
+use kvq_store_redb::database::KVQReDBDatabaseStore;
 use kvq_store_rocksdb::{compat::RocksDBKVQCDB, KVQRocksDBStore};
 
 use crate::utils::block::HeaderList;
-pub type BaseKVQStore = KVQRocksDBStore;
+pub type BaseKVQStore = TxIndexKVQStore;
 pub type BaseCDBStore = RocksDBKVQCDB;
+
+/// Store behind the indexer db, selected with `--indexer-backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxIndexKVQBackend {
+    RocksDB,
+    ReDB,
+    /// Kept in memory and lost on exit, for tests.
+    Memory,
+}
+
+impl TxIndexKVQBackend {
+    pub fn names() -> Vec<String> {
+        vec!["rocksdb".to_string(), "redb".to_string(), "memory".to_string()]
+    }
+}
+
+impl From<&str> for TxIndexKVQBackend {
+    fn from(backend_name: &str) -> Self {
+        match backend_name {
+            "rocksdb" => TxIndexKVQBackend::RocksDB,
+            "redb" => TxIndexKVQBackend::ReDB,
+            "memory" => TxIndexKVQBackend::Memory,
+            _ => panic!("unsupported indexer backend: {:?}", backend_name),
+        }
+    }
+}
+
+/// The indexer db, dispatching every call to the selected backend.
+pub enum TxIndexKVQStore {
+    RocksDB(KVQRocksDBStore),
+    ReDB(KVQReDBDatabaseStore),
+    Memory(KVQImmutableStoreWrapper<KVQSimpleMemoryBackingStore>),
+}
+
+impl TxIndexKVQStore {
+    /// Opens the `backend` store at `path` (ignored by the memory backend).
+    pub fn open<P: AsRef<Path>>(backend: TxIndexKVQBackend, path: P) -> anyhow::Result<Self> {
+        Ok(match backend {
+            TxIndexKVQBackend::RocksDB => TxIndexKVQStore::RocksDB(KVQRocksDBStore::open_default(path)?),
+            TxIndexKVQBackend::ReDB => TxIndexKVQStore::ReDB(KVQReDBDatabaseStore::open(path)?),
+            TxIndexKVQBackend::Memory => TxIndexKVQStore::Memory(KVQImmutableStoreWrapper::new(KVQSimpleMemoryBackingStore::new())),
+        })
+    }
+}
+
+macro_rules! dispatch {
+    ($self:ident, $store:ident => $call:expr) => {
+        match $self {
+            TxIndexKVQStore::RocksDB($store) => $call,
+            TxIndexKVQStore::ReDB($store) => $call,
+            TxIndexKVQStore::Memory($store) => $call,
+        }
+    };
+}
+
+impl KVQBinaryStoreReader for TxIndexKVQStore {
+    fn get_exact_if_exists(&self, key: &Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        dispatch!(self, store => store.get_exact_if_exists(key))
+    }
+
+    fn get_exact(&self, key: &Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        dispatch!(self, store => store.get_exact(key))
+    }
+
+    fn get_many_exact_if_exists(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        dispatch!(self, store => store.get_many_exact_if_exists(keys))
+    }
+
+    fn get_leq(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
+        dispatch!(self, store => store.get_leq(key, fuzzy_bytes))
+    }
+
+    fn get_fuzzy_range_leq_kv(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        dispatch!(self, store => store.get_fuzzy_range_leq_kv(key, fuzzy_bytes))
+    }
+
+    fn get_leq_kv(&self, key: &Vec<u8>, fuzzy_bytes: usize) -> anyhow::Result<Option<KVQPair<Vec<u8>, Vec<u8>>>> {
+        dispatch!(self, store => store.get_leq_kv(key, fuzzy_bytes))
+    }
+
+    fn get_many_leq(&self, keys: &[Vec<u8>], fuzzy_bytes: usize) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        dispatch!(self, store => store.get_many_leq(keys, fuzzy_bytes))
+    }
+
+    fn get_many_leq_kv(&self, keys: &[Vec<u8>], fuzzy_bytes: usize) -> anyhow::Result<Vec<Option<KVQPair<Vec<u8>, Vec<u8>>>>> {
+        dispatch!(self, store => store.get_many_leq_kv(keys, fuzzy_bytes))
+    }
+
+    fn get_range_kv(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        dispatch!(self, store => store.get_range_kv(start, end, start_after, limit))
+    }
+
+    fn get_range_kv_reverse(
+        &self,
+        start: &Vec<u8>,
+        end: Option<&Vec<u8>>,
+        start_after: Option<&Vec<u8>>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<Vec<u8>, Vec<u8>>>> {
+        dispatch!(self, store => store.get_range_kv_reverse(start, end, start_after, limit))
+    }
+}
+
+impl KVQBinaryStoreWriterImmutable for TxIndexKVQStore {
+    fn imm_set(&self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
+        dispatch!(self, store => store.imm_set(key, value))
+    }
+
+    fn imm_set_ref(&self, key: &Vec<u8>, value: &Vec<u8>) -> anyhow::Result<()> {
+        dispatch!(self, store => store.imm_set_ref(key, value))
+    }
+
+    fn imm_set_many_ref<'a>(&self, items: &[KVQPair<&'a Vec<u8>, &'a Vec<u8>>]) -> anyhow::Result<()> {
+        dispatch!(self, store => store.imm_set_many_ref(items))
+    }
+
+    fn imm_set_many_vec(&self, items: Vec<KVQPair<Vec<u8>, Vec<u8>>>) -> anyhow::Result<()> {
+        dispatch!(self, store => store.imm_set_many_vec(items))
+    }
+
+    fn imm_set_many_split_ref(&self, keys: &[Vec<u8>], values: &[Vec<u8>]) -> anyhow::Result<()> {
+        dispatch!(self, store => store.imm_set_many_split_ref(keys, values))
+    }
+
+    fn imm_delete(&self, key: &Vec<u8>) -> anyhow::Result<bool> {
+        dispatch!(self, store => store.imm_delete(key))
+    }
+
+    fn imm_delete_many(&self, keys: &[Vec<u8>]) -> anyhow::Result<Vec<bool>> {
+        dispatch!(self, store => store.imm_delete_many(keys))
+    }
+
+    fn imm_write_batch(&self, batch: KVQWriteBatch) -> anyhow::Result<()> {
+        dispatch!(self, store => store.imm_write_batch(batch))
+    }
+}
+
+impl KVQBinaryStoreWriterAutoImmutable for TxIndexKVQStore {}
+
+impl KVQBinaryStoreImmutable for TxIndexKVQStore {}
 pub struct TxIndexStore {
     pub txstore_db: BaseCDBStore,
     pub history_db: BaseCDBStore,
@@ -2,5 +2,7 @@ pub mod kvstore;
 pub mod traits;
 pub mod table;
 pub mod indexed_block;
+pub mod indexed_action;
 pub mod indexed_block_db;
+pub mod prune;
 pub mod chain;
\ No newline at end of file
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use kvq::traits::{KVQBinaryStoreReader, KVQBinaryStoreWriterImmutable, KVQSerializable, KVQWriteBatch};
+
+use super::{indexed_block::IndexedBlockFull, table::{core::{KVQTable, KVQTableWrapper, TABLE_TYPE_STANDARD}, traits::{get_real_key_at_block, get_table_key_prefix, KVQTableReaderAtBlock}}};
+
+/// Number of undo records pruned per write batch.
+const PRUNE_BATCH_SIZE: usize = 256;
+
+/// Block number of the newest pruned undo record (under key 0). Blocks up to it can no longer be rolled back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedBlockPrunedUntil;
+
+impl KVQTable for IndexedBlockPrunedUntil {
+  type Key = u32;
+  type Value = u64;
+  const TABLE_TYPE: u8 = TABLE_TYPE_STANDARD;
+
+  const TABLE_NAME: &'static str = "indexed_block_pruned_until";
+
+  const TABLE_ID: u32 = 5;
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexedBlockPruneConfig {
+  /// Number of blocks below the latest indexed block that can still be rolled back.
+  pub undo_horizon: u64,
+  /// Ids of the fuzzy block indexed tables whose superseded versions are kept.
+  pub full_history_tables: HashSet<u32>,
+}
+
+impl IndexedBlockPruneConfig {
+  pub fn new(undo_horizon: u64, full_history_tables: &[u32]) -> Self {
+    Self {
+      undo_horizon,
+      full_history_tables: full_history_tables.iter().map(|id| id & 0xfffffff).collect(),
+    }
+  }
+}
+
+/// Returns the newest block whose undo record was pruned, `None` if nothing was pruned.
+pub fn get_pruned_until<S: KVQBinaryStoreReader>(store: &S) -> anyhow::Result<Option<u64>> {
+  KVQTableWrapper::<IndexedBlockPrunedUntil, S>::get_exact_if_exists_at_block(store, 0, &0)
+}
+
+/// Deletes the undo records of blocks more than `undo_horizon` blocks below the latest indexed block, oldest first, and
+/// the versions of fuzzy block indexed keys they make unreachable: every version older than a version written by a
+/// pruned block, and the tombstones written by pruned blocks. Versions listed by a retained undo record are kept, so
+/// rolling its block back still finds them. Reads at or below the pruned blocks no longer see the history of these
+/// tables, but reads at later blocks are unchanged. Returns the number of pruned undo records.
+pub fn prune_undo_history<S: KVQBinaryStoreReader + KVQBinaryStoreWriterImmutable>(store: &S, config: &IndexedBlockPruneConfig) -> anyhow::Result<usize> {
+  let latest_block_number = match KVQTableWrapper::<IndexedBlockFull, S>::get_leq_kv_at_block(store, 0x1fffffffffffffff, &0x1fffffffffffffffu64, 8)? {
+    Some(r) => r.key,
+    None => return Ok(0),
+  };
+  if config.undo_horizon == 0 || latest_block_number < config.undo_horizon {
+    return Ok(0);
+  }
+  let prune_until = latest_block_number - config.undo_horizon;
+  let prefix = get_table_key_prefix::<IndexedBlockFull>(&[]);
+  let pruned_until_key = get_real_key_at_block::<IndexedBlockPrunedUntil>(&0, 0)?;
+  match store.get_prefix_kv(&prefix, None, 1)?.first() {
+    Some(kv) if IndexedBlockFull::get_block_number_from_key(&kv.key)? <= prune_until => {}
+    _ => return Ok(0),
+  }
+  let retained_keys = get_retained_fuzzy_keys(store, prune_until)?;
+  let mut pruned = 0;
+  loop {
+    let page = store.get_prefix_kv(&prefix, None, PRUNE_BATCH_SIZE)?;
+    let mut batch = KVQWriteBatch::new();
+    let mut pruned_until = None;
+    for kv in page.iter() {
+      let block_number = IndexedBlockFull::get_block_number_from_key(&kv.key)?;
+      if block_number > prune_until {
+        break;
+      }
+      let record = IndexedBlockFull::from_bytes(&kv.value)?;
+      for key in record.added_fuzzy_block_keys.iter() {
+        let table_id = u32::from_be_bytes(key[0..4].try_into()?) & 0xfffffff;
+        if !config.full_history_tables.contains(&table_id) {
+          prune_fuzzy_versions(store, key, &retained_keys, &mut batch)?;
+        }
+      }
+      batch.delete(kv.key.clone());
+      pruned_until = Some(block_number);
+      pruned += 1;
+    }
+    let pruned_until = match pruned_until {
+      Some(pruned_until) => pruned_until,
+      None => return Ok(pruned),
+    };
+    batch.set(pruned_until_key.clone(), pruned_until.to_bytes()?);
+    store.imm_write_batch(batch)?;
+  }
+}
+
+// Fuzzy keys versioned at or below `prune_until` that are listed by the undo records of later blocks, e.g. versions
+// written while a module caught up, which are kept until those records are pruned.
+fn get_retained_fuzzy_keys<S: KVQBinaryStoreReader>(store: &S, prune_until: u64) -> anyhow::Result<HashSet<Vec<u8>>> {
+  let prefix = get_table_key_prefix::<IndexedBlockFull>(&[]);
+  let mut keys = HashSet::new();
+  let mut cursor = Some(get_real_key_at_block::<IndexedBlockFull>(&prune_until, prune_until)?);
+  loop {
+    let page = store.get_prefix_kv(&prefix, cursor.as_ref(), PRUNE_BATCH_SIZE)?;
+    let is_last_page = page.len() < PRUNE_BATCH_SIZE;
+    for kv in page {
+      let record = IndexedBlockFull::from_bytes(&kv.value)?;
+      for key in record.added_fuzzy_block_keys {
+        if key.len() >= 12 && u64::from_be_bytes(key[key.len() - 8..].try_into()?) <= prune_until {
+          keys.insert(key);
+        }
+      }
+      cursor = Some(kv.key);
+    }
+    if is_last_page {
+      return Ok(keys);
+    }
+  }
+}
+
+// deletes every version of the fuzzy key older than `key`, and `key` itself if it is a tombstone, except the versions
+// in `retained_keys`
+fn prune_fuzzy_versions<S: KVQBinaryStoreReader>(store: &S, key: &[u8], retained_keys: &HashSet<Vec<u8>>, batch: &mut KVQWriteBatch) -> anyhow::Result<()> {
+  if key.len() < 12 {
+    anyhow::bail!("invalid fuzzy block indexed key {}", hex::encode(key));
+  }
+  let base_len = key.len() - 8;
+  let mut version = u64::from_be_bytes(key[base_len..].try_into()?);
+  let mut search_key = key.to_vec();
+  while version > 0 {
+    search_key[base_len..].copy_from_slice(&(version - 1).to_be_bytes());
+    match store.get_leq_kv(&search_key, 8)? {
+      Some(kv) => {
+        version = u64::from_be_bytes(kv.key[base_len..].try_into()?);
+        if !retained_keys.contains(&kv.key) {
+          batch.delete(kv.key);
+        }
+      }
+      None => break,
+    }
+  }
+  if !retained_keys.contains(key) && store.get_exact_if_exists(&key.to_vec())?.map_or(false, |value| value.is_empty()) {
+    batch.delete(key.to_vec());
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use kvq::traits::KVQBinaryStoreWriterImmutable;
+
+  use super::*;
+  use crate::testing::*;
+
+  fn history_key(block_number: u64) -> Vec<u8> {
+    get_real_key_at_block::<TestHistory<0>>(&0, block_number).unwrap()
+  }
+
+  fn has_version(store: &TestStore, block_number: u64) -> bool {
+    store.get_exact_if_exists(&history_key(block_number)).unwrap().is_some()
+  }
+
+  fn index_blocks(len: usize) -> (Arc<TestChain>, Arc<TestStore>) {
+    let q = TestChain::new(len);
+    let store = new_store();
+    for n in 0..len as u64 {
+      index_block::<TestWorker<0, 0>>(&store, &q, n).unwrap();
+    }
+    (q, store)
+  }
+
+  #[test]
+  fn prune_deletes_versions_only_pruned_blocks_can_read() {
+    let (_, store) = index_blocks(4);
+    assert_eq!(prune_undo_history(store.as_ref(), &IndexedBlockPruneConfig::new(2, &[])).unwrap(), 2);
+    assert!(!has_version(&store, 0));
+    assert!((1..4).all(|n| has_version(&store, n)));
+    assert_eq!(get_pruned_until(store.as_ref()).unwrap(), Some(1));
+  }
+
+  #[test]
+  fn prune_keeps_versions_listed_by_retained_records() {
+    let (_, store) = index_blocks(4);
+    // list the version of block 0 in the record of block 3, as catching up used to
+    let mut batch = KVQWriteBatch::new();
+    for (block_number, keys) in [(0u64, vec![]), (3, vec![history_key(0), history_key(3)])] {
+      let mut record = KVQTableWrapper::<IndexedBlockFull, TestStore>::get_exact_at_block(store.as_ref(), block_number, &block_number).unwrap();
+      record.added_fuzzy_block_keys.retain(|key| !key.starts_with(&get_table_key_prefix::<TestHistory<0>>(&[])));
+      record.added_fuzzy_block_keys.extend(keys);
+      batch.set(get_real_key_at_block::<IndexedBlockFull>(&block_number, block_number).unwrap(), record.to_bytes().unwrap());
+    }
+    store.imm_write_batch(batch).unwrap();
+
+    assert_eq!(prune_undo_history(store.as_ref(), &IndexedBlockPruneConfig::new(2, &[])).unwrap(), 2);
+    assert!((0..4).all(|n| has_version(&store, n)));
+    rollback_to(&store, 3).unwrap();
+    assert!(!has_version(&store, 0) && !has_version(&store, 3));
+  }
+
+  #[test]
+  fn prune_fails_on_invalid_undo_record_keys() {
+    let (_, store) = index_blocks(4);
+    let mut key = get_table_key_prefix::<IndexedBlockFull>(&[]);
+    key.push(0);
+    let mut batch = KVQWriteBatch::new();
+    batch.set(key.clone(), Vec::new());
+    store.imm_write_batch(batch).unwrap();
+
+    let err = prune_undo_history(store.as_ref(), &IndexedBlockPruneConfig::new(2, &[])).unwrap_err();
+    assert!(err.to_string().contains("is invalid"), "{}", err);
+    assert_eq!(IndexedBlockFull::verify_all(store.as_ref()).unwrap().errors, vec![format!("undo record key {} is invalid", hex::encode(&key))]);
+  }
+}
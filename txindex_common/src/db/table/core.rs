@@ -1,10 +1,12 @@
 
-use std::marker::PhantomData;
+use std::{fmt, marker::PhantomData};
 
 use kvq::traits::{KVQBinaryStore, KVQBinaryStoreReader, KVQSerializable};
 
 use super::traits::{KVQTableReaderAtBlock, KVQTableWriterAtBlock};
 
+/// Keeps a version of each key per block it was written at. Keys must serialize to a fixed width, since the block
+/// number is appended to the serialized key.
 pub const TABLE_TYPE_FUZZY_BLOCK_INDEX: u8 = 0;
 pub const TABLE_TYPE_WRITE_ONCE: u8 = 1;
 pub const TABLE_TYPE_STANDARD: u8 = 2;
@@ -21,8 +23,47 @@ Clone + Sync + Sized + Send + PartialEq
   type Value: KVQSerializable;
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct KVQTableInfo {
+  pub name: &'static str,
+  pub table_id: u32,
+  pub table_type: u8,
+}
+
+impl KVQTableInfo {
+  pub fn of<T: KVQTable>() -> Self {
+    Self {
+      name: T::TABLE_NAME,
+      table_id: T::TABLE_ID & 0xfffffff,
+      table_type: T::TABLE_TYPE & 0xf,
+    }
+  }
+}
+
+/// Returned (wrapped in an `anyhow::Error`) by puts that would change an existing value of a write-once table.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KVQWriteOnceViolation {
+  pub table: KVQTableInfo,
+  /// The serialized key, without the table header.
+  pub key: Vec<u8>,
+}
+
+impl KVQWriteOnceViolation {
+  pub fn new<T: KVQTable>(key: &T::Key) -> anyhow::Result<Self> {
+    Ok(Self {
+      table: KVQTableInfo::of::<T>(),
+      key: key.to_bytes()?,
+    })
+  }
+}
 
+impl fmt::Display for KVQWriteOnceViolation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "cannot overwrite key {} of write-once table {}", hex::encode(&self.key), self.table.name)
+  }
+}
 
+impl std::error::Error for KVQWriteOnceViolation {}
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug)]
 pub struct KVQTableWrapper<T: KVQTable, S: KVQBinaryStoreReader> {
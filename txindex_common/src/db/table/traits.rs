@@ -1,10 +1,11 @@
 use bitcoin::hashes::sha256;
 use kvq::traits::{
-    KVQBinaryStore, KVQBinaryStoreReader, KVQPair, KVQSerializable,
+    get_prefix_end_key, KVQBinaryStore, KVQBinaryStoreReader, KVQPair, KVQSerializable,
 };
 
 use super::core::{KVQTable, TABLE_TYPE_FUZZY_BLOCK_INDEX};
 const MAGIC_IMPOSSIBLE_BLOCK_NUMBER: u64 = 0xFFFFFFFFFFFFFFFFu64;
+const FUZZY_SCAN_PAGE_SIZE: usize = 256;
 
 pub const fn get_table_id_hash(name: &'static str) -> u32 {
     if name.len() > 64 {
@@ -39,6 +40,12 @@ pub fn get_real_key_at_block<T: KVQTable>(
     }
     Ok(real_key_bytes)
 }
+/// Returns the raw key prefix shared by every key of the table whose serialized key starts with `key_prefix`.
+pub fn get_table_key_prefix<T: KVQTable>(key_prefix: &[u8]) -> Vec<u8> {
+    let mut prefix = (T::TABLE_ID&(0xfffffffu32) | (((T::TABLE_TYPE&0xf) as u32)<<28u32)).to_be_bytes().to_vec();
+    prefix.extend_from_slice(key_prefix);
+    prefix
+}
 fn resolve_fuzzy_bytes<T: KVQTable>(fuzzy_bytes: usize) -> usize {
     if T::TABLE_TYPE == TABLE_TYPE_FUZZY_BLOCK_INDEX {
         fuzzy_bytes + 8
@@ -54,6 +61,120 @@ pub fn deserialize_raw_key_for_table<T: KVQTable>(raw_key: &[u8]) -> anyhow::Res
     };
     T::Key::from_bytes(key_bytes)
 }
+/// Returns true if `value` marks a key of a fuzzy block indexed table as deleted from its block on.
+/// Deletions are stored as empty values, so values of fuzzy block indexed tables must never serialize to zero bytes.
+pub fn is_fuzzy_tombstone<T: KVQTable>(value: &[u8]) -> bool {
+    T::TABLE_TYPE == TABLE_TYPE_FUZZY_BLOCK_INDEX && value.is_empty()
+}
+fn encode_table_value<T: KVQTable>(value: &T::Value) -> anyhow::Result<Vec<u8>> {
+    let bytes = value.to_bytes()?;
+    if is_fuzzy_tombstone::<T>(&bytes) {
+        anyhow::bail!("values of fuzzy block indexed table {} must not serialize to zero bytes", T::TABLE_NAME);
+    }
+    Ok(bytes)
+}
+// tombstones read as absent
+fn decode_table_value<T: KVQTable>(value: &[u8]) -> anyhow::Result<Option<T::Value>> {
+    if is_fuzzy_tombstone::<T>(value) {
+        Ok(None)
+    } else {
+        Ok(Some(T::Value::from_bytes(value)?))
+    }
+}
+fn decode_table_pair<T: KVQTable>(kv: &KVQPair<Vec<u8>, Vec<u8>>) -> anyhow::Result<Option<KVQPair<KVQTableKeyWithBlockNumber<T>, T::Value>>> {
+    match decode_table_value::<T>(&kv.value)? {
+        Some(value) => Ok(Some(KVQPair {
+            key: KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?,
+            value,
+        })),
+        None => Ok(None),
+    }
+}
+fn decode_scanned_pair<T: KVQTable>(raw_key: &[u8], value: &[u8]) -> anyhow::Result<KVQPair<T::Key, T::Value>> {
+    Ok(KVQPair {
+        key: T::Key::from_bytes(&raw_key[4..])?,
+        value: T::Value::from_bytes(value)?,
+    })
+}
+/// Scans the raw keys in `start..end` of a table, decoding up to `limit` logical keys after `start_after`.
+/// Fuzzy block indexed tables only yield the latest version of each key at or below `block_number`, skipping keys
+/// whose latest version is a tombstone. Their keys must serialize to a fixed width: the versions of a key that is a
+/// prefix of another one would interleave with the other key's versions, so scans fail on keys of different lengths.
+fn scan_raw_range_at_block<S: KVQBinaryStoreReader, T: KVQTable>(
+    s: &S,
+    block_number: u64,
+    start: &Vec<u8>,
+    end: Option<&Vec<u8>>,
+    start_after: Option<&T::Key>,
+    limit: usize,
+) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    // every version of a fuzzy key sorts before key || 0xffffffffffffffff
+    let mut cursor = match start_after {
+        Some(k) => Some(get_real_key_at_block::<T>(k, MAGIC_IMPOSSIBLE_BLOCK_NUMBER)?),
+        None => None,
+    };
+    if T::TABLE_TYPE != TABLE_TYPE_FUZZY_BLOCK_INDEX {
+        return s
+            .get_range_kv(start, end, cursor.as_ref(), limit)?
+            .iter()
+            .map(|kv| decode_scanned_pair::<T>(&kv.key, &kv.value))
+            .collect();
+    }
+
+    let mut results: Vec<KVQPair<T::Key, T::Value>> = Vec::new();
+    // (raw key without the block number, latest value at or below block_number)
+    let mut current: Option<(Vec<u8>, Option<Vec<u8>>)> = None;
+    loop {
+        let page = s.get_range_kv(start, end, cursor.as_ref(), FUZZY_SCAN_PAGE_SIZE)?;
+        let is_last_page = page.len() < FUZZY_SCAN_PAGE_SIZE;
+        for kv in page {
+            if kv.key.len() < 12 {
+                anyhow::bail!("invalid fuzzy table key of length {}", kv.key.len());
+            }
+            match &current {
+                Some((k, _)) if k.len() + 8 != kv.key.len() => anyhow::bail!(
+                    "keys of fuzzy block indexed table {} must serialize to a fixed width, found keys of {} and {} bytes",
+                    T::TABLE_NAME,
+                    k.len() - 4,
+                    kv.key.len() - 12
+                ),
+                _ => {}
+            }
+            let split = kv.key.len() - 8;
+            let version = u64::from_be_bytes(kv.key[split..].try_into()?);
+            let is_new_key = match &current {
+                Some((k, _)) => k.as_slice() != &kv.key[..split],
+                None => true,
+            };
+            if is_new_key {
+                if let Some((k, Some(v))) = current.take() {
+                    results.push(decode_scanned_pair::<T>(&k, &v)?);
+                    if results.len() == limit {
+                        return Ok(results);
+                    }
+                }
+                current = Some((kv.key[..split].to_vec(), None));
+            }
+            if version <= block_number {
+                if let Some((_, latest)) = current.as_mut() {
+                    // a tombstone hides the older versions
+                    *latest = if kv.value.is_empty() { None } else { Some(kv.value) };
+                }
+            }
+            cursor = Some(kv.key);
+        }
+        if is_last_page {
+            break;
+        }
+    }
+    if let Some((k, Some(v))) = current {
+        results.push(decode_scanned_pair::<T>(&k, &v)?);
+    }
+    Ok(results)
+}
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct KVQDecodedRawTableKey {
     pub table_id: u32,
@@ -186,11 +307,9 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
         key: &T::Key,
     ) -> anyhow::Result<Option<T::Value>> {
         let r = s.get_exact_if_exists(&get_real_key_at_block::<T>(key, block_number)?)?;
-        if r.is_some() {
-            let result = T::Value::from_bytes(&r.unwrap())?;
-            Ok(Some(result))
-        } else {
-            Ok(None)
+        match r {
+            Some(v) => decode_table_value::<T>(&v),
+            None => Ok(None),
         }
     }
     fn get_exact_at_block(s: &S, block_number: u64, key: &T::Key) -> anyhow::Result<T::Value> {
@@ -209,10 +328,13 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
             resolve_fuzzy_bytes::<T>(fuzzy_bytes),
         )?;
         match r {
-            Some(kv) => Ok(Some(KVQPair {
-                key: deserialize_raw_key_for_table::<T>(&kv.key)?,
-                value: T::Value::from_bytes(&kv.value)?,
-            })),
+            Some(kv) => match decode_table_value::<T>(&kv.value)? {
+                Some(value) => Ok(Some(KVQPair {
+                    key: deserialize_raw_key_for_table::<T>(&kv.key)?,
+                    value,
+                })),
+                None => Ok(None),
+            },
             None => Ok(None),
         }
     }
@@ -234,6 +356,24 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
         Ok(values?)
     }
 
+    fn get_many_exact_if_exists_at_block(
+        s: &S,
+        block_number: u64,
+        keys: &[T::Key],
+    ) -> anyhow::Result<Vec<Option<T::Value>>> {
+        let keys_bytes = keys
+            .iter()
+            .map(|k| get_real_key_at_block::<T>(k, block_number))
+            .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+        s.get_many_exact_if_exists(&keys_bytes)?
+            .iter()
+            .map(|r| match r {
+                Some(v) => decode_table_value::<T>(v),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
     fn get_leq_at_block(
         s: &S,
         block_number: u64,
@@ -245,7 +385,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
             resolve_fuzzy_bytes::<T>(fuzzy_bytes),
         )?;
         match r {
-            Some(v) => Ok(Some(T::Value::from_bytes(&v)?)),
+            Some(v) => decode_table_value::<T>(&v),
             None => Ok(None),
         }
     }
@@ -265,7 +405,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
             .iter()
             .map(|r| {
                 Ok(match r {
-                    Some(v) => Some(T::Value::from_bytes(v)?),
+                    Some(v) => decode_table_value::<T>(v)?,
                     None => None,
                 })
             })
@@ -289,10 +429,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
                 .iter()
                 .map(|r| {
                     Ok(match r {
-                        Some(kv) => Some(KVQPair {
-                            key: KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?,
-                            value: T::Value::from_bytes(&kv.value)?,
-                        }),
+                        Some(kv) => decode_table_pair::<T>(kv)?,
                         None => None,
                     })
                 })
@@ -308,26 +445,95 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
     ) -> anyhow::Result<Vec<KVQPair<KVQTableKeyWithBlockNumber<T>, T::Value>>> {
         let key = get_real_key_at_block::<T>(key, block_number)?;
         s.get_fuzzy_range_leq_kv(&key, resolve_fuzzy_bytes::<T>(fuzzy_bytes))?
-            .into_iter()
-            .map(|kv| {
-                Ok(KVQPair {
-                    key: KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?,
-                    value: T::Value::from_bytes(&kv.value)?,
-                })
-            })
+            .iter()
+            .filter_map(|kv| decode_table_pair::<T>(kv).transpose())
             .collect()
     }
 
+    /// Returns up to `limit` pairs whose serialized key starts with `key_prefix`, in key order,
+    /// resuming after `start_after` when given.
+    fn scan_prefix_at_block(
+        s: &S,
+        block_number: u64,
+        key_prefix: &[u8],
+        start_after: Option<&T::Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+        let start = get_table_key_prefix::<T>(key_prefix);
+        let end = get_prefix_end_key(&start);
+        scan_raw_range_at_block::<S, T>(s, block_number, &start, end.as_ref(), start_after, limit)
+    }
+
+    /// Returns up to `limit` pairs with `start <= key < end` (`None` for unbounded), in key order,
+    /// resuming after `start_after` when given.
+    fn scan_range_at_block(
+        s: &S,
+        block_number: u64,
+        start: Option<&T::Key>,
+        end: Option<&T::Key>,
+        start_after: Option<&T::Key>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<KVQPair<T::Key, T::Value>>> {
+        let start = match start {
+            Some(k) => get_table_key_prefix::<T>(&k.to_bytes()?),
+            None => get_table_key_prefix::<T>(&[]),
+        };
+        let end = match end {
+            Some(k) => Some(get_table_key_prefix::<T>(&k.to_bytes()?)),
+            None => get_prefix_end_key(&get_table_key_prefix::<T>(&[])),
+        };
+        scan_raw_range_at_block::<S, T>(s, block_number, &start, end.as_ref(), start_after, limit)
+    }
+
+    /// Returns every stored version of `key` with `from_block <= block_number <= to_block`,
+    /// oldest first, as (block number, value) pairs, with `None` for the blocks that deleted the key.
+    /// Only fuzzy block indexed tables keep versions.
+    fn get_history_at_blocks(
+        s: &S,
+        key: &T::Key,
+        from_block: u64,
+        to_block: u64,
+    ) -> anyhow::Result<Vec<KVQPair<u64, Option<T::Value>>>> {
+        if T::TABLE_TYPE != TABLE_TYPE_FUZZY_BLOCK_INDEX {
+            anyhow::bail!("table {} does not keep a history of its values", T::TABLE_NAME);
+        }
+        if from_block > to_block {
+            return Ok(Vec::new());
+        }
+        let start = get_real_key_at_block::<T>(key, from_block)?;
+        let end = if to_block == MAGIC_IMPOSSIBLE_BLOCK_NUMBER {
+            get_prefix_end_key(&get_table_key_prefix::<T>(&key.to_bytes()?))
+        } else {
+            Some(get_real_key_at_block::<T>(key, to_block + 1)?)
+        };
+        let mut results: Vec<KVQPair<u64, Option<T::Value>>> = Vec::new();
+        let mut cursor: Option<Vec<u8>> = None;
+        loop {
+            let page = s.get_range_kv(&start, end.as_ref(), cursor.as_ref(), FUZZY_SCAN_PAGE_SIZE)?;
+            let is_last_page = page.len() < FUZZY_SCAN_PAGE_SIZE;
+            for kv in page {
+                let k = KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?;
+                results.push(KVQPair {
+                    key: k.block_number,
+                    value: decode_table_value::<T>(&kv.value)?,
+                });
+                cursor = Some(kv.key);
+            }
+            if is_last_page {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
     fn get_exact_if_exists_combo_at_block(
         s: &S,
         key: &KVQTableKeyWithBlockNumber<T>,
     ) -> anyhow::Result<Option<T::Value>> {
         let r = s.get_exact_if_exists(&key.to_bytes()?)?;
-        if r.is_some() {
-            let result = T::Value::from_bytes(&r.unwrap())?;
-            Ok(Some(result))
-        } else {
-            Ok(None)
+        match r {
+            Some(v) => decode_table_value::<T>(&v),
+            None => Ok(None),
         }
     }
     fn get_exact_combo_at_block(s: &S, key: &KVQTableKeyWithBlockNumber<T>) -> anyhow::Result<T::Value> {
@@ -345,10 +551,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
             resolve_fuzzy_bytes::<T>(fuzzy_bytes),
         )?;
         match r {
-            Some(kv) => Ok(Some(KVQPair {
-                key: KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?,
-                value: T::Value::from_bytes(&kv.value)?,
-            })),
+            Some(kv) => decode_table_pair::<T>(&kv),
             None => Ok(None),
         }
     }
@@ -379,7 +582,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
             resolve_fuzzy_bytes::<T>(fuzzy_bytes),
         )?;
         match r {
-            Some(v) => Ok(Some(T::Value::from_bytes(&v)?)),
+            Some(v) => decode_table_value::<T>(&v),
             None => Ok(None),
         }
     }
@@ -398,7 +601,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
             .iter()
             .map(|r| {
                 Ok(match r {
-                    Some(v) => Some(T::Value::from_bytes(v)?),
+                    Some(v) => decode_table_value::<T>(v)?,
                     None => None,
                 })
             })
@@ -421,10 +624,7 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
                 .iter()
                 .map(|r| {
                     Ok(match r {
-                        Some(kv) => Some(KVQPair {
-                            key: KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?,
-                            value: T::Value::from_bytes(&kv.value)?,
-                        }),
+                        Some(kv) => decode_table_pair::<T>(kv)?,
                         None => None,
                     })
                 })
@@ -439,13 +639,8 @@ pub trait KVQTableReaderAtBlock<S: KVQBinaryStoreReader, T: KVQTable> {
     ) -> anyhow::Result<Vec<KVQPair<KVQTableKeyWithBlockNumber<T>, T::Value>>> {
         let key = key.to_bytes()?;
         s.get_fuzzy_range_leq_kv(&key, resolve_fuzzy_bytes::<T>(fuzzy_bytes))?
-            .into_iter()
-            .map(|kv| {
-                Ok(KVQPair {
-                    key: KVQTableKeyWithBlockNumber::<T>::from_bytes(&kv.key)?,
-                    value: T::Value::from_bytes(&kv.value)?,
-                })
-            })
+            .iter()
+            .filter_map(|kv| decode_table_pair::<T>(kv).transpose())
             .collect()
     }
 }
@@ -461,7 +656,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
     ) -> anyhow::Result<()> {
         s.set(
             get_real_key_at_block::<T>(key, block_number)?,
-            value.to_bytes()?,
+            encode_table_value::<T>(value)?,
         )
     }
     fn set_at_block(
@@ -472,7 +667,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
     ) -> anyhow::Result<()> {
         s.set(
             get_real_key_at_block::<T>(&key, block_number)?,
-            value.to_bytes()?,
+            encode_table_value::<T>(&value)?,
         )
     }
 
@@ -486,7 +681,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
             .map(|kv| {
                 Ok(KVQPair {
                     key: get_real_key_at_block::<T>(kv.key, block_number)?,
-                    value: kv.value.to_bytes()?,
+                    value: encode_table_value::<T>(kv.value)?,
                 })
             })
             .collect();
@@ -503,7 +698,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
             .map(|kv| {
                 Ok(KVQPair {
                     key: get_real_key_at_block::<T>(&kv.key, block_number)?,
-                    value: kv.value.to_bytes()?,
+                    value: encode_table_value::<T>(&kv.value)?,
                 })
             })
             .collect();
@@ -528,6 +723,14 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
         Ok(results)
     }
 
+    /// Marks `key` of a fuzzy block indexed table as deleted from `block_number` on, keeping its older versions.
+    fn set_tombstone_at_block(s: &mut S, block_number: u64, key: &T::Key) -> anyhow::Result<()> {
+        if T::TABLE_TYPE != TABLE_TYPE_FUZZY_BLOCK_INDEX {
+            anyhow::bail!("table {} does not keep versions, delete its keys instead", T::TABLE_NAME);
+        }
+        s.set(get_real_key_at_block::<T>(key, block_number)?, Vec::new())
+    }
+
     fn set_many_split_ref_at_block(
         s: &mut S,
         block_number: u64,
@@ -541,7 +744,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
         let mut values_bytes: Vec<Vec<u8>> = Vec::with_capacity(values.len());
         for (k, v) in keys.iter().zip(values.iter()) {
             keys_bytes.push(get_real_key_at_block::<T>(k, block_number)?);
-            values_bytes.push(v.to_bytes()?);
+            values_bytes.push(encode_table_value::<T>(v)?);
         }
 
         s.set_many_split_ref(&keys_bytes, &values_bytes)
@@ -556,7 +759,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
     ) -> anyhow::Result<()> {
         s.set(
             key.to_bytes()?,
-            value.to_bytes()?,
+            encode_table_value::<T>(value)?,
         )
     }
     fn set_combo_at_block(
@@ -566,7 +769,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
     ) -> anyhow::Result<()> {
         s.set(
             key.to_bytes()?,
-            value.to_bytes()?,
+            encode_table_value::<T>(&value)?,
         )
     }
 
@@ -579,7 +782,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
             .map(|kv| {
                 Ok(KVQPair {
                     key: kv.key.to_bytes()?,
-                    value: kv.value.to_bytes()?,
+                    value: encode_table_value::<T>(kv.value)?,
                 })
             })
             .collect();
@@ -595,7 +798,7 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
             .map(|kv| {
                 Ok(KVQPair {
                     key: kv.key.to_bytes()?,
-                    value: kv.value.to_bytes()?,
+                    value: encode_table_value::<T>(&kv.value)?,
                 })
             })
             .collect();
@@ -631,9 +834,73 @@ pub trait KVQTableWriterAtBlock<S: KVQBinaryStore, T: KVQTable>:
         let mut values_bytes: Vec<Vec<u8>> = Vec::with_capacity(values.len());
         for (k, v) in keys.iter().zip(values.iter()) {
             keys_bytes.push(k.to_bytes()?);
-            values_bytes.push(v.to_bytes()?);
+            values_bytes.push(encode_table_value::<T>(v)?);
         }
 
         s.set_many_split_ref(&keys_bytes, &values_bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use kvq::memory::simple::KVQSimpleMemoryBackingStore;
+    use kvq::traits::KVQBinaryStoreWriter;
+
+    use super::*;
+    use crate::db::table::core::KVQTableWrapper;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TestFuzzyTable;
+
+    impl KVQTable for TestFuzzyTable {
+        type Key = Vec<u8>;
+        type Value = Vec<u8>;
+        const TABLE_TYPE: u8 = TABLE_TYPE_FUZZY_BLOCK_INDEX;
+        const TABLE_NAME: &'static str = "test_fuzzy";
+        const TABLE_ID: u32 = 0x1234;
+    }
+
+    type TestTable = KVQTableWrapper<TestFuzzyTable, KVQSimpleMemoryBackingStore>;
+
+    fn store_with(versions: &[(&[u8], u64, &[u8])]) -> KVQSimpleMemoryBackingStore {
+        let mut store = KVQSimpleMemoryBackingStore::new();
+        for (key, block_number, value) in versions {
+            let raw_key = get_real_key_at_block::<TestFuzzyTable>(&key.to_vec(), *block_number).unwrap();
+            store.set(raw_key, value.to_vec()).unwrap();
+        }
+        store
+    }
+
+    fn scan(store: &KVQSimpleMemoryBackingStore, block_number: u64, start_after: Option<&[u8]>, limit: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let start_after = start_after.map(|k| k.to_vec());
+        TestTable::scan_prefix_at_block(store, block_number, &[], start_after.as_ref(), limit)
+            .unwrap()
+            .into_iter()
+            .map(|kv| (kv.key, kv.value))
+            .collect()
+    }
+
+    #[test]
+    fn scan_returns_latest_version_of_fixed_width_keys() {
+        let store = store_with(&[
+            (b"aa", 1, b"a1"),
+            (b"aa", 5, b"a5"),
+            (b"ab", 3, b""),
+            (b"ab", 2, b"b2"),
+            (b"ba", 4, b"c4"),
+        ]);
+        let kv = |k: &[u8], v: &[u8]| (k.to_vec(), v.to_vec());
+        assert_eq!(scan(&store, 2, None, 10), vec![kv(b"aa", b"a1"), kv(b"ab", b"b2")]);
+        assert_eq!(scan(&store, 10, None, 10), vec![kv(b"aa", b"a5"), kv(b"ba", b"c4")]);
+        assert_eq!(scan(&store, 10, None, 1), vec![kv(b"aa", b"a5")]);
+        assert_eq!(scan(&store, 10, Some(b"aa"), 10), vec![kv(b"ba", b"c4")]);
+    }
+
+    #[test]
+    fn scan_rejects_keys_of_different_widths() {
+        // the versions of "a\0" sort between the versions of "a"
+        let store = store_with(&[(b"a", 1, b"x"), (b"a\0", 2, b"y"), (b"a", 0x100, b"z")]);
+        let err = TestTable::scan_prefix_at_block(&store, 10, &[], None, 10).unwrap_err();
+        assert!(err.to_string().contains("fixed width"), "{}", err);
+    }
+}
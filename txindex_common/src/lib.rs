@@ -4,5 +4,8 @@ pub mod daemon;
 pub mod rpc;
 pub mod db;
 pub mod worker;
+pub mod module;
 pub mod utils;
-pub mod api;
\ No newline at end of file
+pub mod api;
+#[cfg(test)]
+mod testing;
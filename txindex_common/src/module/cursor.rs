@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+use kvq::{cache::KVQBinaryStoreCached, traits::{get_prefix_end_key, KVQBinaryStoreImmutable, KVQSerializable, KVQWriteBatch}};
+
+use crate::{db::{chain::TxIndexChainAPI, indexed_block::IndexedBlockFull, indexed_block_db::IndexedBlockDBStore, prune::get_pruned_until, table::{core::{KVQTable, KVQTableWrapper, TABLE_TYPE_STANDARD}, traits::{get_real_key_at_block, get_table_key_prefix, KVQTableReaderAtBlock}}}, worker::{context::TxIndexBlockContext, traits::TxIndexWorker}};
+
+use super::traits::TxIndexModule;
+
+/// Last block processed by each module, keyed by module id.
+/// Cursor updates are recorded in the block's `IndexedBlockFull` like any other standard key, so rollbacks rewind them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxIndexModuleSyncCursor;
+
+impl KVQTable for TxIndexModuleSyncCursor {
+  type Key = u32;
+  type Value = u64;
+  const TABLE_TYPE: u8 = TABLE_TYPE_STANDARD;
+
+  const TABLE_NAME: &'static str = "module_sync_cursor";
+
+  const TABLE_ID: u32 = 1;
+}
+
+// First block module `M` has not processed yet.
+fn get_next_block_number<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, M: TxIndexModule<KVQ, T>>(cursor: Option<u64>) -> u64 {
+  cursor.map_or(0, |last| last + 1).max(M::Worker::ACTIVATION_HEIGHT)
+}
+
+// Runs the worker of module `M` and stamps the actions it emits with the module id.
+fn process_module_block<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, M: TxIndexModule<KVQ, T>>(
+  db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
+  q: &Arc<T>,
+  ctx: &TxIndexBlockContext,
+) -> anyhow::Result<()> {
+  let first_action = db.actions.len();
+  M::Worker::process_block(db, Arc::clone(q), ctx)?;
+  db.actions[first_action..].iter_mut().for_each(|action| action.value.worker_id = M::MODULE_ID);
+  Ok(())
+}
+
+/// Runs module `M` for the block of `ctx`, which must follow the last block it processed: modules that are behind are
+/// backfilled by `catch_up_module` before new blocks are indexed. Blocks outside the worker's active range are skipped.
+pub fn sync_module_to_block<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, M: TxIndexModule<KVQ, T>>(
+  db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
+  q: &Arc<T>,
+  ctx: &TxIndexBlockContext,
+) -> anyhow::Result<()> {
+  let block_number = ctx.block_number;
+  if !M::Worker::is_active_at_block(block_number) {
+    return Ok(());
+  }
+  // a cursor ahead of the block was seeded by `seed_module_cursors`, which is not recorded in the undo records, before
+  // the block was rolled back
+  let next_block_number = get_next_block_number::<KVQ, T, M>(db.get::<TxIndexModuleSyncCursor>(&M::MODULE_ID)?);
+  if next_block_number < block_number {
+    anyhow::bail!(
+      "module {} is behind: its next block is {}, not {} (modules are caught up when the indexer starts)",
+      M::MODULE_NAME,
+      next_block_number,
+      block_number
+    );
+  }
+  process_module_block::<KVQ, T, M>(db, q, ctx)?;
+  db.put::<TxIndexModuleSyncCursor>(&M::MODULE_ID, &block_number)
+}
+
+/// Sets the cursor of every module to the latest indexed block if blocks were indexed but no module has a cursor yet,
+/// i.e. the db was indexed before modules kept cursors and every module already processed every indexed block.
+/// Modules added at the same time are not backfilled.
+pub fn seed_module_cursors<KVQ: KVQBinaryStoreImmutable>(store: &KVQ, module_ids: &[u32]) -> anyhow::Result<()> {
+  let latest_block_number = match IndexedBlockFull::get_latest_block_number(store)? {
+    Some(block_number) => block_number,
+    None => return Ok(()),
+  };
+  let prefix = get_table_key_prefix::<TxIndexModuleSyncCursor>(&[]);
+  if !store.get_prefix_kv(&prefix, None, 1)?.is_empty() {
+    return Ok(());
+  }
+  log::info!("seeding the sync cursors of {} modules at block {}", module_ids.len(), latest_block_number);
+  let mut batch = KVQWriteBatch::with_capacity(module_ids.len());
+  for module_id in module_ids {
+    batch.set(get_real_key_at_block::<TxIndexModuleSyncCursor>(module_id, latest_block_number)?, latest_block_number.to_bytes()?);
+  }
+  store.imm_write_batch(batch)
+}
+
+/// Processes every indexed block module `M` is missing, from its activation height or the block after its cursor up to
+/// the latest indexed block. The changes made at each block are added to the first `IndexedBlockFull` at or after it,
+/// i.e. the record of the block itself, or of the block it was indexed with if it was indexed without a record of its
+/// own, so they are rolled back with it. Blocks whose undo record was pruned are saved without one.
+/// Modules must only write their own tables: catching up reads the current state of the other tables.
+pub fn catch_up_module<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, M: TxIndexModule<KVQ, T>>(
+  store: &Arc<KVQ>,
+  q: &Arc<T>,
+) -> anyhow::Result<()> {
+  let latest_block_number = match IndexedBlockFull::get_latest_block_number(store.as_ref())? {
+    Some(block_number) => block_number,
+    None => return Ok(()),
+  };
+  let cursor = KVQTableWrapper::<TxIndexModuleSyncCursor, KVQ>::get_exact_if_exists_at_block(store.as_ref(), 0, &M::MODULE_ID)?;
+  let next_block_number = get_next_block_number::<KVQ, T, M>(cursor);
+  let last_block_number = match M::Worker::END_HEIGHT {
+    Some(end) => latest_block_number.min(end),
+    None => latest_block_number,
+  };
+  if next_block_number > last_block_number {
+    return Ok(());
+  }
+  log::info!("module {} is catching up from block {} to {}", M::MODULE_NAME, next_block_number, last_block_number);
+  let pruned_until = get_pruned_until(store.as_ref())?;
+  let mut block_number = next_block_number;
+  while block_number <= last_block_number {
+    if pruned_until.map_or(false, |pruned_until| block_number <= pruned_until) {
+      let block = q.get_block(block_number)?;
+      let ctx = TxIndexBlockContext::new_from_chain(q.as_ref(), block_number, &block)?;
+      let mut db = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(store)), block_number, &block);
+      process_module_block::<KVQ, T, M>(&mut db, q, &ctx)?;
+      db.put::<TxIndexModuleSyncCursor>(&M::MODULE_ID, &block_number)?;
+      IndexedBlockFull::save_unrecorded_from_db_store(db)?;
+      block_number += 1;
+      continue;
+    }
+    let record = get_first_record_from::<KVQ>(store.as_ref(), block_number)?
+      .ok_or_else(|| anyhow::anyhow!("block {} has no undo record at or after it", block_number))?;
+    let record_block_number = record.metadata.block_number;
+    let mut db = IndexedBlockDBStore {
+      store: KVQBinaryStoreCached::new(Arc::clone(store)),
+      block_number,
+      metadata: record.metadata,
+      actions: Vec::new(),
+    };
+    let last_block_of_record = record_block_number.min(last_block_number);
+    for block_number in block_number..=last_block_of_record {
+      let block = q.get_block(block_number)?;
+      let ctx = TxIndexBlockContext::new_from_chain(q.as_ref(), block_number, &block)?;
+      db.block_number = block_number;
+      process_module_block::<KVQ, T, M>(&mut db, q, &ctx)?;
+    }
+    db.put::<TxIndexModuleSyncCursor>(&M::MODULE_ID, &last_block_of_record)?;
+    db.block_number = record_block_number;
+    record.amend_from_db_store(db)?;
+    if last_block_of_record % 1000 == 0 {
+      log::debug!("module {} caught up to block {}", M::MODULE_NAME, last_block_of_record);
+    }
+    block_number = last_block_of_record + 1;
+  }
+  Ok(())
+}
+
+// The undo record of the first block at or after `block_number` that has one.
+fn get_first_record_from<KVQ: KVQBinaryStoreImmutable>(store: &KVQ, block_number: u64) -> anyhow::Result<Option<IndexedBlockFull>> {
+  let start = get_real_key_at_block::<IndexedBlockFull>(&block_number, block_number)?;
+  let end = get_prefix_end_key(&get_table_key_prefix::<IndexedBlockFull>(&[]));
+  match store.get_range_kv(&start, end.as_ref(), None, 1)?.into_iter().next() {
+    Some(kv) => Ok(Some(IndexedBlockFull::from_bytes(&kv.value)?)),
+    None => Ok(None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use kvq::traits::KVQBinaryStoreWriterImmutable;
+
+  use super::*;
+  use crate::{db::indexed_block_db::IndexedBlockDBStoreReader, module::set::TxIndexModuleSetWorker, testing::*};
+
+  type ModuleA = TestModule<0, 0>;
+  type ModuleB = TestModule<1, 2>;
+  type SetA = TxIndexModuleSetWorker<(ModuleA,)>;
+  type SetAB = TxIndexModuleSetWorker<(ModuleA, ModuleB)>;
+
+  // the store without the undo records, whose lists are ordered differently when amended
+  fn dump_tables(store: &TestStore) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let records = get_table_key_prefix::<IndexedBlockFull>(&[]);
+    dump(store).into_iter().filter(|(k, _)| !k.starts_with(&records)).collect()
+  }
+
+  fn get_cursor(store: &TestStore, module_id: u32) -> Option<u64> {
+    KVQTableWrapper::<TxIndexModuleSyncCursor, TestStore>::get_exact_if_exists_at_block(store, 0, &module_id).unwrap()
+  }
+
+  #[test]
+  fn catch_up_matches_indexing_with_the_module() {
+    let q = TestChain::new(8);
+    let store = new_store();
+    for n in 0..6 {
+      index_block::<SetA>(&store, &q, n).unwrap();
+    }
+    <SetAB as TxIndexWorker<TestStore, TestChain>>::catch_up(&store, Arc::clone(&q)).unwrap();
+    assert_eq!(get_cursor(&store, ModuleB::MODULE_ID), Some(5));
+    index_block::<SetAB>(&store, &q, 6).unwrap();
+
+    let expected = new_store();
+    for n in 0..7 {
+      index_block::<SetAB>(&expected, &q, n).unwrap();
+    }
+    assert_eq!(dump_tables(&store), dump_tables(&expected));
+    assert_eq!(IndexedBlockFull::verify_all(store.as_ref()).unwrap().errors, Vec::<String>::new());
+    // the backfilled changes are rolled back with the blocks they were made at
+    for block_number in [4, 2, 0] {
+      rollback_to(&store, block_number).unwrap();
+      rollback_to(&expected, block_number).unwrap();
+      assert_eq!(dump_tables(&store), dump_tables(&expected));
+    }
+    assert!(dump(&store).is_empty());
+  }
+
+  // Indexes blocks `from` to `to` under the record of block `to`, as `update_with_block` does for missing blocks.
+  fn index_blocks_together<W: TxIndexWorker<TestStore, TestChain>>(store: &Arc<TestStore>, q: &Arc<TestChain>, from: u64, to: u64) {
+    let mut db = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(store)), to, &q.blocks[to as usize]);
+    for block_number in from..=to {
+      let ctx = TxIndexBlockContext::new_from_chain(q.as_ref(), block_number, &q.blocks[block_number as usize]).unwrap();
+      db.block_number = block_number;
+      W::process_block(&mut db, Arc::clone(q), &ctx).unwrap();
+    }
+    IndexedBlockFull::save_from_db_store(db).unwrap();
+  }
+
+  #[test]
+  fn catch_up_adds_blocks_indexed_without_records_to_the_next_record() {
+    let q = TestChain::new(7);
+    let store = new_store();
+    let expected = new_store();
+    for n in 0..2 {
+      index_block::<SetA>(&store, &q, n).unwrap();
+      index_block::<SetAB>(&expected, &q, n).unwrap();
+    }
+    // blocks 2 to 4 are saved with the record of block 5
+    index_blocks_together::<SetA>(&store, &q, 2, 5);
+    index_blocks_together::<SetAB>(&expected, &q, 2, 5);
+
+    <SetAB as TxIndexWorker<TestStore, TestChain>>::catch_up(&store, Arc::clone(&q)).unwrap();
+    assert_eq!(get_cursor(&store, ModuleB::MODULE_ID), Some(5));
+    assert_eq!(IndexedBlockFull::get_latest_block_number(store.as_ref()).unwrap(), Some(5));
+    assert_eq!(IndexedBlockFull::verify_all(store.as_ref()).unwrap().errors, Vec::<String>::new());
+    assert_eq!(dump_tables(&store), dump_tables(&expected));
+
+    index_block::<SetAB>(&store, &q, 6).unwrap();
+    index_block::<SetAB>(&expected, &q, 6).unwrap();
+    assert_eq!(dump_tables(&store), dump_tables(&expected));
+    rollback_to(&store, 2).unwrap();
+    rollback_to(&expected, 2).unwrap();
+    assert_eq!(dump_tables(&store), dump_tables(&expected));
+    assert_eq!(get_cursor(&store, ModuleB::MODULE_ID), None);
+  }
+
+  #[test]
+  fn catch_up_saves_pruned_blocks_without_records() {
+    let q = TestChain::new(4);
+    let store = new_store();
+    for n in 0..4 {
+      index_block::<SetA>(&store, &q, n).unwrap();
+    }
+    // drop the records of blocks 0 to 2, as pruning does
+    let mut batch = KVQWriteBatch::new();
+    for n in 0..3u64 {
+      batch.delete(get_real_key_at_block::<IndexedBlockFull>(&n, n).unwrap());
+    }
+    batch.set(get_real_key_at_block::<crate::db::prune::IndexedBlockPrunedUntil>(&0, 0).unwrap(), 2u64.to_bytes().unwrap());
+    store.imm_write_batch(batch).unwrap();
+
+    <SetAB as TxIndexWorker<TestStore, TestChain>>::catch_up(&store, Arc::clone(&q)).unwrap();
+    assert_eq!(get_cursor(&store, ModuleB::MODULE_ID), Some(3));
+    assert_eq!(IndexedBlockFull::get_latest_block_number(store.as_ref()).unwrap(), Some(3));
+    rollback_to(&store, 3).unwrap();
+    assert_eq!(get_cursor(&store, ModuleB::MODULE_ID), Some(2));
+    assert_eq!(KVQTableWrapper::<TestCounters<1>, TestStore>::get_exact_if_exists_at_block(store.as_ref(), 0, &0).unwrap(), Some(1));
+  }
+
+  #[test]
+  fn modules_behind_fail_instead_of_skipping_blocks() {
+    let q = TestChain::new(4);
+    let store = new_store();
+    for n in 0..3 {
+      index_block::<SetA>(&store, &q, n).unwrap();
+    }
+    let err = index_block::<SetAB>(&store, &q, 3).unwrap_err();
+    assert!(err.to_string().contains("behind"), "{}", err);
+  }
+
+  #[test]
+  fn cursors_are_seeded_for_dbs_indexed_without_them() {
+    let q = TestChain::new(4);
+    let store = new_store();
+    for n in 0..3 {
+      index_block::<TestWorker<0, 0>>(&store, &q, n).unwrap();
+    }
+    <SetAB as TxIndexWorker<TestStore, TestChain>>::catch_up(&store, Arc::clone(&q)).unwrap();
+    assert_eq!(get_cursor(&store, ModuleA::MODULE_ID), Some(2));
+    assert_eq!(get_cursor(&store, ModuleB::MODULE_ID), Some(2));
+    assert_eq!(KVQTableWrapper::<TestCounters<0>, TestStore>::get_exact_if_exists_at_block(store.as_ref(), 0, &0).unwrap(), Some(3));
+
+    index_block::<SetAB>(&store, &q, 3).unwrap();
+    rollback_to(&store, 3).unwrap();
+    assert_eq!(get_cursor(&store, ModuleA::MODULE_ID), Some(2));
+    // the seeded cursors are not recorded, so they stay ahead of the blocks rolled back below them
+    rollback_to(&store, 1).unwrap();
+    assert_eq!(get_cursor(&store, ModuleA::MODULE_ID), Some(2));
+    index_block::<SetAB>(&store, &q, 1).unwrap();
+    assert_eq!(KVQTableWrapper::<TestCounters<0>, TestStore>::get_exact_if_exists_at_block(store.as_ref(), 0, &0).unwrap(), Some(2));
+    assert_eq!(get_cursor(&store, ModuleA::MODULE_ID), Some(1));
+  }
+
+  #[test]
+  fn module_actions_carry_the_module_id() {
+    let q = TestChain::new(4);
+    let store = new_store();
+    for n in 0..3 {
+      index_block::<SetA>(&store, &q, n).unwrap();
+    }
+    <SetAB as TxIndexWorker<TestStore, TestChain>>::catch_up(&store, Arc::clone(&q)).unwrap();
+    index_block::<SetAB>(&store, &q, 3).unwrap();
+
+    let reader = IndexedBlockDBStoreReader::new(Arc::clone(&store), None);
+    for block_number in 2..4 {
+      let ids = reader.get_actions_by_block(block_number, None, 10).unwrap().into_iter().map(|a| (a.id.worker_id, a.id.action_index, a.action.worker_id)).collect::<Vec<_>>();
+      let mut expected = vec![(ModuleA::MODULE_ID, 0, ModuleA::MODULE_ID), (ModuleB::MODULE_ID, 0, ModuleB::MODULE_ID)];
+      expected.sort();
+      assert_eq!(ids, expected);
+    }
+  }
+}
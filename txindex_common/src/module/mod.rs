@@ -0,0 +1,3 @@
+pub mod traits;
+pub mod set;
+pub mod cursor;
\ No newline at end of file
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+
+use kvq::{cache::KVQBinaryStoreCached, traits::KVQBinaryStoreImmutable};
+
+use crate::{api::{response::TxIndexAPIResponse, traits::TxIndexAPIHandler}, chain::Network, db::{chain::TxIndexChainAPI, indexed_action::{IndexedBlockActionsByBlock, IndexedBlockActionsByTxid, IndexedBlockActionsByWorker}, indexed_block::IndexedBlockFull, indexed_block_db::{IndexedBlockDBStore, IndexedBlockDBStoreReader}, kvstore::BaseKVQStore, prune::IndexedBlockPrunedUntil, table::core::KVQTableInfo}, worker::{context::TxIndexBlockContext, traits::TxIndexWorker}};
+
+use super::{cursor::{catch_up_module, seed_module_cursors, sync_module_to_block, TxIndexModuleSyncCursor}, traits::TxIndexModule};
+
+/// A group of modules served by one txindex server, e.g. `(TxCounterModule<KVQ, T>, MyModule<KVQ, T>)`.
+/// Blocks are passed to the workers in order, modules that are behind are backfilled by `catch_up` when the indexer
+/// starts, and API requests are routed by `PATH_SLUG`.
+pub trait TxIndexModuleSet<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> {
+  fn module_names() -> Vec<&'static str>;
+  fn module_ids() -> Vec<(u32, &'static str)>;
+  fn tables() -> Vec<KVQTableInfo>;
+  fn validate_module_tables() -> anyhow::Result<()>;
+  fn process_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, ctx: &TxIndexBlockContext) -> anyhow::Result<()>;
+  /// Seeds the module cursors of a db indexed before modules kept cursors, then backfills every module up to the
+  /// latest indexed block.
+  fn catch_up(store: &Arc<KVQ>, q: Arc<T>) -> anyhow::Result<()>;
+  /// Calls `on_rollback` on the workers that are active at `block_number`.
+  fn on_rollback(block_number: u64, block: &IndexedBlockFull) -> anyhow::Result<()>;
+  /// Returns `None` if no module handles `pathname`.
+  fn handle_get_request(
+    network: Network,
+    pathname: String,
+    query_string: String,
+    chain: Arc<T>,
+    indexer_db: IndexedBlockDBStoreReader<BaseKVQStore>,
+  ) -> Option<TxIndexAPIResponse>;
+
+  /// Checks that module names and ids are unique.
+  fn validate_modules() -> anyhow::Result<()> {
+    let ids = Self::module_ids();
+    for (i, (id, name)) in ids.iter().enumerate() {
+      if let Some((_, other)) = ids[..i].iter().find(|(other_id, other_name)| other_id == id || other_name == name) {
+        anyhow::bail!("module {} has the same id ({:#x}) or name as module {}", name, id, other);
+      }
+    }
+    Ok(())
+  }
+
+  /// Checks that no two registered tables share a key prefix.
+  fn validate_tables() -> anyhow::Result<()> {
+    Self::validate_module_tables()?;
+    let mut seen: HashMap<(u32, u8), &'static str> = HashMap::new();
+    let core_tables = [
+      KVQTableInfo::of::<IndexedBlockFull>(),
+      KVQTableInfo::of::<TxIndexModuleSyncCursor>(),
+      KVQTableInfo::of::<IndexedBlockActionsByBlock>(),
+      KVQTableInfo::of::<IndexedBlockActionsByTxid>(),
+      KVQTableInfo::of::<IndexedBlockActionsByWorker>(),
+      KVQTableInfo::of::<IndexedBlockPrunedUntil>(),
+    ];
+    for table in core_tables {
+      seen.insert((table.table_id, table.table_type), table.name);
+    }
+    for table in Self::tables() {
+      match seen.get(&(table.table_id, table.table_type)) {
+        Some(name) if *name != table.name => {
+          anyhow::bail!("table {} has the same id ({:#x}) and type as table {}", table.name, table.table_id, name);
+        }
+        Some(_) => {}
+        None => {
+          seen.insert((table.table_id, table.table_type), table.name);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Root worker that runs every module of the set.
+pub struct TxIndexModuleSetWorker<M> {
+  _modules: PhantomData<M>,
+}
+
+impl<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, M: TxIndexModuleSet<KVQ, T>> TxIndexWorker<KVQ, T> for TxIndexModuleSetWorker<M> {
+  fn process_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, ctx: &TxIndexBlockContext) -> anyhow::Result<()> {
+    M::process_block(db, q, ctx)
+  }
+  fn catch_up(store: &Arc<KVQ>, q: Arc<T>) -> anyhow::Result<()> {
+    M::catch_up(store, q)
+  }
+  fn on_rollback(block_number: u64, block: &IndexedBlockFull) -> anyhow::Result<()> {
+    M::on_rollback(block_number, block)
+  }
+}
+
+macro_rules! impl_module_set_for_tuple {
+  ($($m:ident),+) => {
+    impl<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, $($m: TxIndexModule<KVQ, T>),+> TxIndexModuleSet<KVQ, T> for ($($m,)+) {
+      fn module_names() -> Vec<&'static str> {
+        vec![$($m::MODULE_NAME),+]
+      }
+      fn module_ids() -> Vec<(u32, &'static str)> {
+        vec![$(($m::MODULE_ID, $m::MODULE_NAME)),+]
+      }
+      fn tables() -> Vec<KVQTableInfo> {
+        let mut tables = Vec::new();
+        $(tables.extend($m::tables());)+
+        tables
+      }
+      fn validate_module_tables() -> anyhow::Result<()> {
+        $($m::validate_tables()?;)+
+        Ok(())
+      }
+      fn process_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, ctx: &TxIndexBlockContext) -> anyhow::Result<()> {
+        $(sync_module_to_block::<KVQ, T, $m>(db, &q, ctx)?;)+
+        Ok(())
+      }
+      fn catch_up(store: &Arc<KVQ>, q: Arc<T>) -> anyhow::Result<()> {
+        seed_module_cursors(store.as_ref(), &[$($m::MODULE_ID),+])?;
+        $(catch_up_module::<KVQ, T, $m>(store, &q)?;)+
+        Ok(())
+      }
+      fn on_rollback(block_number: u64, block: &IndexedBlockFull) -> anyhow::Result<()> {
+        $(
+          if <$m::Worker as TxIndexWorker<KVQ, T>>::is_active_at_block(block_number) {
+            <$m::Worker as TxIndexWorker<KVQ, T>>::on_rollback(block_number, block)?;
+          }
+        )+
+        Ok(())
+      }
+      fn handle_get_request(
+        network: Network,
+        pathname: String,
+        query_string: String,
+        chain: Arc<T>,
+        indexer_db: IndexedBlockDBStoreReader<BaseKVQStore>,
+      ) -> Option<TxIndexAPIResponse> {
+        $(
+          if pathname.starts_with(<$m::API as TxIndexAPIHandler<T>>::PATH_SLUG) {
+            return Some(<$m::API as TxIndexAPIHandler<T>>::handle_get_request(network, pathname, query_string, chain, indexer_db));
+          }
+        )+
+        None
+      }
+    }
+  };
+}
+
+impl_module_set_for_tuple!(A);
+impl_module_set_for_tuple!(A, B);
+impl_module_set_for_tuple!(A, B, C);
+impl_module_set_for_tuple!(A, B, C, D);
+impl_module_set_for_tuple!(A, B, C, D, E);
+impl_module_set_for_tuple!(A, B, C, D, E, F);
+impl_module_set_for_tuple!(A, B, C, D, E, F, G);
+impl_module_set_for_tuple!(A, B, C, D, E, F, G, H);
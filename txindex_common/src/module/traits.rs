@@ -0,0 +1,27 @@
+use kvq::traits::KVQBinaryStoreImmutable;
+
+use crate::{api::traits::TxIndexAPIHandler, db::{chain::TxIndexChainAPI, table::{core::{KVQTableInfo, TABLE_TYPE_FUZZY_BLOCK_INDEX}, traits::get_table_id_hash}}, worker::traits::TxIndexWorker};
+
+/// A self-contained indexer: the worker that fills its tables and the API handler that serves them.
+pub trait TxIndexModule<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> {
+  const MODULE_NAME: &'static str;
+  /// Identifies the module's sync cursor and the actions it emits: it replaces the worker id of every action emitted by
+  /// the module's worker.
+  const MODULE_ID: u32 = get_table_id_hash(Self::MODULE_NAME);
+
+  type Worker: TxIndexWorker<KVQ, T>;
+  type API: TxIndexAPIHandler<T>;
+
+  /// The tables written by the module's worker.
+  fn tables() -> Vec<KVQTableInfo>;
+
+  /// Checks that workers declaring a block number suffix only write to fuzzy block indexed tables.
+  fn validate_tables() -> anyhow::Result<()> {
+    if <Self::Worker as TxIndexWorker<KVQ, T>>::PROCESSING_MODE.has_block_number_suffix() {
+      if let Some(table) = Self::tables().iter().find(|t| t.table_type != TABLE_TYPE_FUZZY_BLOCK_INDEX) {
+        anyhow::bail!("module {} uses a block number suffix but table {} is not fuzzy block indexed", Self::MODULE_NAME, table.name);
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,179 @@
+//! In-memory chain, store and modules shared by the unit tests.
+use std::{marker::PhantomData, sync::Arc};
+
+use bitcoin::{absolute::LockTime, blockdata::constants::genesis_block, hashes::Hash, Block, BlockHash, Transaction};
+use kvq::{cache::KVQBinaryStoreCached, memory::{immutable::KVQImmutableStoreWrapper, simple::KVQSimpleMemoryBackingStore}, traits::{KVQBinaryStoreReader, KVQBinaryStoreWriterImmutable}};
+
+use crate::{
+  api::{response::TxIndexAPIResponse, traits::TxIndexAPIHandler},
+  chain::Network,
+  db::{
+    chain::TxIndexChainAPI,
+    indexed_action::IndexedBlockAction,
+    indexed_block::IndexedBlockFull,
+    indexed_block_db::{IndexedBlockDBStore, IndexedBlockDBStoreReader},
+    kvstore::BaseKVQStore,
+    table::{core::{KVQTable, KVQTableInfo, KVQTableWrapper, TABLE_TYPE_FUZZY_BLOCK_INDEX, TABLE_TYPE_STANDARD, TABLE_TYPE_WRITE_ONCE}, traits::KVQTableReaderAtBlock},
+  },
+  module::traits::TxIndexModule,
+  worker::{context::TxIndexBlockContext, traits::TxIndexWorker},
+};
+
+pub type TestStore = KVQImmutableStoreWrapper<KVQSimpleMemoryBackingStore>;
+
+pub fn new_store() -> Arc<TestStore> {
+  Arc::new(KVQImmutableStoreWrapper::new(KVQSimpleMemoryBackingStore::new()))
+}
+
+/// Every key and value of the store, in key order.
+pub fn dump(store: &TestStore) -> Vec<(Vec<u8>, Vec<u8>)> {
+  store.get_range_kv(&Vec::new(), None, None, usize::MAX).unwrap().into_iter().map(|kv| (kv.key, kv.value)).collect()
+}
+
+/// A chain of coinbase-only blocks, each with a distinct coinbase.
+pub struct TestChain {
+  pub blocks: Vec<Block>,
+}
+
+impl TestChain {
+  pub fn new(len: usize) -> Arc<Self> {
+    let blocks = (0..len)
+      .map(|i| {
+        let mut block = genesis_block(bitcoin::Network::Regtest);
+        block.txdata[0].lock_time = LockTime::from_consensus(i as u32);
+        block.header.time += i as u32;
+        block
+      })
+      .collect();
+    Arc::new(Self { blocks })
+  }
+  fn find_block(&self, hash: &[u8; 32]) -> anyhow::Result<&Block> {
+    self.blocks.iter().find(|b| b.block_hash().as_byte_array() == hash).ok_or_else(|| anyhow::anyhow!("block not found"))
+  }
+}
+
+impl TxIndexChainAPI for TestChain {
+  fn get_transaction(&self, txid: [u8; 32]) -> anyhow::Result<Transaction> {
+    self.blocks.iter().flat_map(|b| b.txdata.iter()).find(|tx| tx.txid().as_byte_array() == &txid).cloned().ok_or_else(|| anyhow::anyhow!("transaction not found"))
+  }
+  fn get_block(&self, block_number: u64) -> anyhow::Result<Block> {
+    self.blocks.get(block_number as usize).cloned().ok_or_else(|| anyhow::anyhow!("block {} not found", block_number))
+  }
+  fn get_blockhash(&self, block_number: u64) -> anyhow::Result<BlockHash> {
+    Ok(self.get_block(block_number)?.block_hash())
+  }
+  fn get_block_by_hash(&self, hash: [u8; 32]) -> anyhow::Result<Block> {
+    self.find_block(&hash).cloned()
+  }
+  fn get_latest_block(&self) -> anyhow::Result<Block> {
+    self.blocks.last().cloned().ok_or_else(|| anyhow::anyhow!("empty chain"))
+  }
+  fn get_network(&self) -> Network {
+    Network::Regtest
+  }
+  fn get_bitcoin_network(&self) -> bitcoin::Network {
+    bitcoin::Network::Regtest
+  }
+}
+
+/// Processes block `block_number` of `q` with worker `W` and saves it with its undo record.
+pub fn index_block<W: TxIndexWorker<TestStore, TestChain>>(store: &Arc<TestStore>, q: &Arc<TestChain>, block_number: u64) -> anyhow::Result<Vec<IndexedBlockAction>> {
+  let block = q.get_block(block_number)?;
+  let ctx = TxIndexBlockContext::new_from_chain(q.as_ref(), block_number, &block)?;
+  let mut db = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(store)), block_number, &block);
+  W::process_block(&mut db, Arc::clone(q), &ctx)?;
+  IndexedBlockFull::save_from_db_store(db)
+}
+
+/// Undoes every indexed block from `block_number` on, newest first.
+pub fn rollback_to(store: &TestStore, block_number: u64) -> anyhow::Result<()> {
+  while let Some(latest) = IndexedBlockFull::get_latest_block_number(store)? {
+    if latest < block_number {
+      break;
+    }
+    let record = KVQTableWrapper::<IndexedBlockFull, TestStore>::get_exact_at_block(store, latest, &latest)?;
+    store.imm_write_batch(record.get_undo_batch()?)?;
+  }
+  Ok(())
+}
+
+/// Standard table of test module `N`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCounters<const N: u32>;
+
+impl<const N: u32> KVQTable for TestCounters<N> {
+  type Key = u32;
+  type Value = u64;
+  const TABLE_TYPE: u8 = TABLE_TYPE_STANDARD;
+  const TABLE_NAME: &'static str = "test_counters";
+  const TABLE_ID: u32 = 0x100 + N;
+}
+
+/// Fuzzy block indexed table of test module `N`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestHistory<const N: u32>;
+
+impl<const N: u32> KVQTable for TestHistory<N> {
+  type Key = u32;
+  type Value = u64;
+  const TABLE_TYPE: u8 = TABLE_TYPE_FUZZY_BLOCK_INDEX;
+  const TABLE_NAME: &'static str = "test_history";
+  const TABLE_ID: u32 = 0x200 + N;
+}
+
+/// Write-once table of test module `N`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestRecords<const N: u32>;
+
+impl<const N: u32> KVQTable for TestRecords<N> {
+  type Key = u32;
+  type Value = u64;
+  const TABLE_TYPE: u8 = TABLE_TYPE_WRITE_ONCE;
+  const TABLE_NAME: &'static str = "test_records";
+  const TABLE_ID: u32 = 0x300 + N;
+}
+
+/// Counts the blocks it processed, keeps the last block number (deleting it every fourth block), writes a version of
+/// its history key per block and emits an action per block.
+pub struct TestWorker<const N: u32, const ACTIVATION: u64>;
+
+impl<const N: u32, const ACTIVATION: u64> TxIndexWorker<TestStore, TestChain> for TestWorker<N, ACTIVATION> {
+  const ACTIVATION_HEIGHT: u64 = ACTIVATION;
+
+  fn process_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<TestStore>>, _q: Arc<TestChain>, ctx: &TxIndexBlockContext) -> anyhow::Result<()> {
+    let count = db.get::<TestCounters<N>>(&0)?.unwrap_or(0);
+    db.put::<TestCounters<N>>(&0, &(count + 1))?;
+    if ctx.block_number % 4 == 3 {
+      db.delete::<TestCounters<N>>(&1)?;
+    } else {
+      db.put::<TestCounters<N>>(&1, &ctx.block_number)?;
+    }
+    db.put::<TestHistory<N>>(&0, &(ctx.block_number + 1))?;
+    db.emit_action_from_txid(&ctx.block.txdata[0].txid(), 0, N, &ctx.block_number)
+  }
+}
+
+pub struct TestAPI;
+
+impl TxIndexAPIHandler<TestChain> for TestAPI {
+  const PATH_SLUG: &'static str = "/indexer/test/";
+
+  fn handle_get_request(_network: Network, _pathname: String, _query_string: String, _chain: Arc<TestChain>, _indexer_db: IndexedBlockDBStoreReader<BaseKVQStore>) -> TxIndexAPIResponse {
+    TxIndexAPIResponse { status: 404, content_type: "application/json".to_string(), body: Vec::new() }
+  }
+}
+
+pub struct TestModule<const N: u32, const ACTIVATION: u64> {
+  _worker: PhantomData<TestWorker<N, ACTIVATION>>,
+}
+
+impl<const N: u32, const ACTIVATION: u64> TxIndexModule<TestStore, TestChain> for TestModule<N, ACTIVATION> {
+  const MODULE_NAME: &'static str = if N == 0 { "test_a" } else { "test_b" };
+
+  type Worker = TestWorker<N, ACTIVATION>;
+  type API = TestAPI;
+
+  fn tables() -> Vec<KVQTableInfo> {
+    vec![KVQTableInfo::of::<TestCounters<N>>(), KVQTableInfo::of::<TestHistory<N>>()]
+  }
+}
@@ -1,10 +1,12 @@
-use crate::chain::{BlockHash, BlockHeader};
-use bitcoin::Block;
+use crate::chain::{BlockHash, BlockHeader, Network, AUXPOW_VERSION_FLAG};
+use bitcoin::consensus::encode::{self, Decodable};
+use bitcoin::{Block, Transaction, VarInt};
 use serde::{Deserialize, Serialize};
 use txindex_errors::core::*;
 
 use std::collections::HashMap;
 use std::fmt;
+use std::io::Cursor;
 use std::iter::FromIterator;
 use std::slice;
 use time::format_description::well_known::Rfc3339;
@@ -27,6 +29,49 @@ pub struct BlockEntry {
 
 pub type SizedBlock = (Block, u32);
 
+/// Decodes a serialized block. On networks with merge mining, the AuxPoW section that follows
+/// the header of merge-mined blocks (parent coinbase, merkle branches and parent header) is
+/// skipped, since `bitcoin::Block` has no place for it.
+pub fn deserialize_block(bytes: &[u8], network: Network) -> Result<Block> {
+    let mut cursor = Cursor::new(bytes);
+    let header =
+        BlockHeader::consensus_decode(&mut cursor).chain_err(|| "failed to parse block header")?;
+    if network.has_auxpow() && header.version.to_consensus() & AUXPOW_VERSION_FLAG != 0 {
+        skip_auxpow(&mut cursor).chain_err(|| "failed to parse AuxPoW header")?;
+    }
+    let txdata = Vec::<Transaction>::consensus_decode(&mut cursor)
+        .chain_err(|| "failed to parse block transactions")?;
+    if cursor.position() != bytes.len() as u64 {
+        return Err(format!(
+            "{} trailing bytes after block {}",
+            bytes.len() as u64 - cursor.position(),
+            header.block_hash()
+        )
+        .into());
+    }
+    Ok(Block { header, txdata })
+}
+
+fn skip_auxpow(cursor: &mut Cursor<&[u8]>) -> std::result::Result<(), encode::Error> {
+    // parent coinbase, parent block hash, coinbase and chain merkle branches, parent header
+    Transaction::consensus_decode(cursor)?;
+    BlockHash::consensus_decode(cursor)?;
+    skip_merkle_branch(cursor)?;
+    skip_merkle_branch(cursor)?;
+    BlockHeader::consensus_decode(cursor)?;
+    Ok(())
+}
+
+fn skip_merkle_branch(cursor: &mut Cursor<&[u8]>) -> std::result::Result<(), encode::Error> {
+    let branch_len = VarInt::consensus_decode(cursor)?.0;
+    for _ in 0..branch_len {
+        <[u8; 32]>::consensus_decode(cursor)?;
+    }
+    // side mask / chain index
+    u32::consensus_decode(cursor)?;
+    Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BlockId {
     pub height: usize,
@@ -345,3 +390,71 @@ impl BlockMeta {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::block::Version;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::consensus::encode::serialize;
+
+    // Dogecoin mainnet block version of merge-mined blocks (chain id 0x62, AuxPoW flag, version 4).
+    const AUXPOW_BLOCK_VERSION: i32 = 0x0062_0104;
+
+    // Lays out a merge-mined block the way dogecoind serializes it: header, AuxPoW section
+    // (parent coinbase, parent hash, coinbase branch, chain branch, parent header), txdata.
+    fn merge_mined_block() -> (Block, Vec<u8>) {
+        let parent = genesis_block(bitcoin::Network::Bitcoin);
+        let mut block = genesis_block(bitcoin::Network::Bitcoin);
+        block.header.version = Version::from_consensus(AUXPOW_BLOCK_VERSION);
+
+        let mut bytes = serialize(&block.header);
+        bytes.extend(serialize(&parent.txdata[0]));
+        bytes.extend(serialize(&parent.block_hash()));
+        bytes.extend(serialize(&VarInt(2)));
+        bytes.extend([0x11; 32]);
+        bytes.extend([0x22; 32]);
+        bytes.extend(serialize(&0u32));
+        bytes.extend(serialize(&VarInt(0)));
+        bytes.extend(serialize(&0u32));
+        bytes.extend(serialize(&parent.header));
+        bytes.extend(serialize(&block.txdata));
+        (block, bytes)
+    }
+
+    #[test]
+    fn deserialize_block_skips_auxpow() {
+        let (block, bytes) = merge_mined_block();
+        let parsed = deserialize_block(&bytes, Network::Dogecoin).unwrap();
+        assert_eq!(parsed, block);
+        assert_eq!(parsed.block_hash(), block.block_hash());
+    }
+
+    #[test]
+    fn deserialize_block_without_auxpow() {
+        let block = genesis_block(bitcoin::Network::Bitcoin);
+        let bytes = serialize(&block);
+        assert_eq!(deserialize_block(&bytes, Network::Dogecoin).unwrap(), block);
+        assert_eq!(deserialize_block(&bytes, Network::Bitcoin).unwrap(), block);
+    }
+
+    #[test]
+    fn deserialize_block_rejects_truncated_records() {
+        let (_, bytes) = merge_mined_block();
+        // cut inside the AuxPoW section, inside the txdata, and right after the AuxPoW section
+        let txdata_len = serialize(&genesis_block(bitcoin::Network::Bitcoin).txdata).len();
+        for len in [100, bytes.len() - 1, bytes.len() - txdata_len] {
+            assert!(deserialize_block(&bytes[..len], Network::Dogecoin).is_err());
+        }
+    }
+
+    #[test]
+    fn deserialize_block_rejects_trailing_bytes() {
+        let (_, bytes) = merge_mined_block();
+        // without AuxPoW support the parent coinbase is read as txdata and the rest is left over
+        assert!(deserialize_block(&bytes, Network::Bitcoin).is_err());
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert!(deserialize_block(&bytes, Network::Dogecoin).is_err());
+    }
+}
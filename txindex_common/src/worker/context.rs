@@ -0,0 +1,132 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use bitcoin::{hashes::Hash, Block, OutPoint, Script, Transaction, TxOut, Txid};
+
+use crate::{db::chain::TxIndexChainAPI, utils::transaction::is_coinbase};
+
+/// A block handed to `TxIndexWorker::process_block`, with the outputs spent by its inputs already resolved.
+#[derive(Debug, Clone)]
+pub struct TxIndexBlockContext<'a> {
+  pub block_number: u64,
+  pub block: &'a Block,
+  /// Outputs spent by the block's inputs. May contain outputs spent by other blocks of the same batch.
+  pub prevouts: Cow<'a, HashMap<OutPoint, TxOut>>,
+}
+
+impl<'a> TxIndexBlockContext<'a> {
+  pub fn new(block_number: u64, block: &'a Block, prevouts: Cow<'a, HashMap<OutPoint, TxOut>>) -> Self {
+    Self {
+      block_number,
+      block,
+      prevouts,
+    }
+  }
+  /// Resolves the prevouts of `block` from the block itself and the chain, for blocks outside of the indexer's batch.
+  /// Fails if the chain cannot be read.
+  pub fn new_from_chain<T: TxIndexChainAPI>(q: &T, block_number: u64, block: &'a Block) -> anyhow::Result<Self> {
+    let block_txs: HashMap<Txid, &Transaction> = block.txdata.iter().map(|tx| (tx.txid(), tx)).collect();
+    let mut prevouts: HashMap<OutPoint, TxOut> = HashMap::new();
+    for tx in block.txdata.iter() {
+      for txin in tx.input.iter() {
+        if is_coinbase(txin) || prevouts.contains_key(&txin.previous_output) {
+          continue;
+        }
+        let vout = txin.previous_output.vout as usize;
+        let prevout = match block_txs.get(&txin.previous_output.txid) {
+          Some(prev_tx) => prev_tx.output.get(vout).cloned(),
+          None => q.get_transaction(txin.previous_output.txid.to_raw_hash().to_byte_array())?.output.get(vout).cloned(),
+        };
+        if let Some(prevout) = prevout {
+          prevouts.insert(txin.previous_output, prevout);
+        }
+      }
+    }
+    Ok(Self::new(block_number, block, Cow::Owned(prevouts)))
+  }
+
+  pub fn get_prevout(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+    self.prevouts.get(outpoint)
+  }
+  /// The output spent by each input of `tx`, `None` for coinbase inputs and outputs that could not be found.
+  pub fn get_prevouts(&self, tx: &Transaction) -> Vec<Option<TxOut>> {
+    tx.input.iter().map(|txin| {
+      if is_coinbase(txin) {
+        None
+      } else {
+        self.get_prevout(&txin.previous_output).cloned()
+      }
+    }).collect()
+  }
+  /// The fee paid by `tx` in satoshis, `None` for coinbase transactions or if a prevout is missing.
+  pub fn get_fee(&self, tx: &Transaction) -> Option<u64> {
+    if tx.is_coinbase() {
+      return None;
+    }
+    let mut input_value = 0u64;
+    for txin in tx.input.iter() {
+      input_value += self.get_prevout(&txin.previous_output)?.value.to_sat();
+    }
+    let output_value: u64 = tx.output.iter().map(|txout| txout.value.to_sat()).sum();
+    input_value.checked_sub(output_value)
+  }
+  /// The fees paid by every transaction of the block, in block order.
+  pub fn get_fees(&self) -> Vec<Option<u64>> {
+    self.block.txdata.iter().map(|tx| self.get_fee(tx)).collect()
+  }
+  pub fn get_transaction_contexts(&self) -> Vec<TxIndexTransactionContext> {
+    self.block.txdata.iter().enumerate().map(|(tx_index, tx)| TxIndexTransactionContext {
+      block_number: self.block_number,
+      block: self.block,
+      tx_index,
+      tx,
+      prevouts: self.get_prevouts(tx),
+      fee: self.get_fee(tx),
+    }).collect()
+  }
+}
+
+/// A transaction handed to `TxIndexWorker::process_transaction`.
+#[derive(Debug, Clone)]
+pub struct TxIndexTransactionContext<'a> {
+  pub block_number: u64,
+  pub block: &'a Block,
+  pub tx_index: usize,
+  pub tx: &'a Transaction,
+  /// The output spent by each input, `None` for coinbase inputs and outputs that could not be found.
+  pub prevouts: Vec<Option<TxOut>>,
+  /// The fee paid in satoshis, `None` for the coinbase transaction or if a prevout is missing.
+  pub fee: Option<u64>,
+}
+
+impl<'a> TxIndexTransactionContext<'a> {
+  /// The script of the output spent by each input, `None` where the prevout is unknown.
+  pub fn get_spent_scripts(&self) -> Vec<Option<&Script>> {
+    self.prevouts.iter().map(|prevout| prevout.as_ref().map(|p| p.script_pubkey.as_script())).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bitcoin::{OutPoint, TxIn};
+
+  use super::TxIndexBlockContext;
+  use crate::testing::TestChain;
+
+  #[test]
+  fn new_from_chain_resolves_prevouts_of_the_block_and_fails_on_chain_errors() {
+    let q = TestChain::new(1);
+    let mut block = q.blocks[0].clone();
+    let coinbase = block.txdata[0].clone();
+    let mut spend = coinbase.clone();
+    spend.input = vec![TxIn { previous_output: OutPoint::new(coinbase.txid(), 0), ..Default::default() }];
+    block.txdata.push(spend.clone());
+
+    let ctx = TxIndexBlockContext::new_from_chain(q.as_ref(), 1, &block).unwrap();
+    assert_eq!(ctx.get_prevouts(&spend), vec![Some(coinbase.output[0].clone())]);
+
+    let mut orphan = spend.clone();
+    orphan.input[0].previous_output = OutPoint::new(spend.txid(), 0);
+    block.txdata = vec![coinbase, orphan];
+    assert!(TxIndexBlockContext::new_from_chain(q.as_ref(), 1, &block).is_err());
+  }
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use bitcoin::{Script, Transaction, TxOut};
+use kvq::{cache::KVQBinaryStoreCached, traits::KVQBinaryStoreImmutable};
+
+use crate::db::{chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStore};
+
+/// An unconfirmed transaction handed to `TxIndexMempoolWorker::process_mempool_transaction`.
+#[derive(Debug, Clone)]
+pub struct TxIndexMempoolTransactionContext<'a> {
+  /// The block the transaction would be confirmed in, i.e. the tip height + 1.
+  pub block_number: u64,
+  pub tx: &'a Transaction,
+  /// The output spent by each input (confirmed or unconfirmed), `None` if it could not be found.
+  pub prevouts: Vec<Option<TxOut>>,
+  /// The fee paid in satoshis, `None` if a prevout is missing.
+  pub fee: Option<u64>,
+}
+
+impl<'a> TxIndexMempoolTransactionContext<'a> {
+  /// The script of the output spent by each input, `None` where the prevout is unknown.
+  pub fn get_spent_scripts(&self) -> Vec<Option<&Script>> {
+    self.prevouts.iter().map(|prevout| prevout.as_ref().map(|p| p.script_pubkey.as_script())).collect()
+  }
+}
+
+/// Indexes unconfirmed transactions into the pending overlay, a cache over the confirmed indexer db that is never
+/// saved. The overlay is rebuilt when the tip changes or transactions leave the mempool, and only new transactions
+/// are processed otherwise; parents are always processed before their children.
+/// `db.block_number` is the next block, so fuzzy tables see pending writes as the newest version. Emitted actions are
+/// discarded. API handlers read the overlay with `IndexedBlockDBStoreReader::with_pending`.
+pub trait TxIndexMempoolWorker<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> {
+  /// Set to `false` to skip building the overlay.
+  const ENABLED: bool = true;
+
+  fn process_mempool_transaction(
+    db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
+    q: Arc<T>,
+    ctx: &TxIndexMempoolTransactionContext,
+  ) -> anyhow::Result<()>;
+}
+
+/// No mempool indexing.
+impl<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> TxIndexMempoolWorker<KVQ, T> for () {
+  const ENABLED: bool = false;
+
+  fn process_mempool_transaction(
+    _db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
+    _q: Arc<T>,
+    _ctx: &TxIndexMempoolTransactionContext,
+  ) -> anyhow::Result<()> {
+    Ok(())
+  }
+}
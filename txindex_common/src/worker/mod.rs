@@ -1,2 +1,4 @@
 pub mod traits;
-pub mod mode;
\ No newline at end of file
+pub mod mode;
+pub mod context;
+pub mod mempool;
\ No newline at end of file
@@ -1,3 +1,7 @@
+/// How the framework drives a worker.
+/// `Block` workers implement `process_block`, `Transactions` workers implement `process_transaction` and are called once
+/// per transaction in block order. The `*WithBlockNumberSuffix` variants are driven the same way and declare that
+/// every table of the worker is suffixed with the block number (fuzzy block indexed), which the module set checks.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TxWorkerProcessingMode {
   Block,
@@ -6,4 +10,11 @@ pub enum TxWorkerProcessingMode {
   TransactionsWithBlockNumberSuffix,
 }
 
-
+impl TxWorkerProcessingMode {
+  pub fn is_per_transaction(&self) -> bool {
+    matches!(self, Self::Transactions | Self::TransactionsWithBlockNumberSuffix)
+  }
+  pub fn has_block_number_suffix(&self) -> bool {
+    matches!(self, Self::BlockWithBlockNumberSuffix | Self::TransactionsWithBlockNumberSuffix)
+  }
+}
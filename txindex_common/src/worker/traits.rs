@@ -1,10 +1,84 @@
-
 use std::sync::Arc;
 
-use bitcoin::Block;
 use kvq::{cache::KVQBinaryStoreCached, traits::KVQBinaryStoreImmutable};
+use rayon::prelude::*;
+
+use crate::db::{chain::TxIndexChainAPI, indexed_block::IndexedBlockFull, indexed_block_db::IndexedBlockDBStore};
 
-use crate::db::{chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStore};
+use super::{context::{TxIndexBlockContext, TxIndexTransactionContext}, mode::TxWorkerProcessingMode};
 pub trait TxIndexWorker<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI> {
-  fn process_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, block_number: u64, block: &Block) -> anyhow::Result<()>;
-}
\ No newline at end of file
+  /// First block passed to `process_block`.
+  const ACTIVATION_HEIGHT: u64 = 0;
+  /// Last block passed to `process_block`, `None` to follow the tip forever.
+  const END_HEIGHT: Option<u64> = None;
+  const PROCESSING_MODE: TxWorkerProcessingMode = TxWorkerProcessingMode::Block;
+  /// Set by per-transaction workers whose transactions never read what other transactions of the same block write,
+  /// so they can be processed in parallel.
+  const TRANSACTION_INDEPENDENT: bool = false;
+
+  fn is_active_at_block(block_number: u64) -> bool {
+    block_number >= Self::ACTIVATION_HEIGHT && Self::END_HEIGHT.map_or(true, |end| block_number <= end)
+  }
+  fn process_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, ctx: &TxIndexBlockContext) -> anyhow::Result<()> {
+    if !Self::PROCESSING_MODE.is_per_transaction() {
+      anyhow::bail!("workers in {:?} mode must implement process_block", Self::PROCESSING_MODE);
+    }
+    process_block_transactions::<KVQ, T, Self>(db, q, ctx)
+  }
+  fn process_transaction(_db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, _q: Arc<T>, _ctx: &TxIndexTransactionContext) -> anyhow::Result<()> {
+    anyhow::bail!("workers in {:?} mode must implement process_transaction", Self::PROCESSING_MODE)
+  }
+  /// Called when the indexer starts, after orphaned blocks are rolled back, to backfill state the worker is missing
+  /// for the blocks already indexed.
+  fn catch_up(_store: &Arc<KVQ>, _q: Arc<T>) -> anyhow::Result<()> {
+    Ok(())
+  }
+  /// Called after `block_number` was rolled back by a reorg, newest block first, once its keys are restored.
+  /// `block` is the record of what was undone. Workers that keep state outside their tables can reset it here.
+  fn on_rollback(_block_number: u64, _block: &IndexedBlockFull) -> anyhow::Result<()> {
+    Ok(())
+  }
+}
+
+/// Calls `W::process_transaction` for every transaction of the block, in parallel if the worker is transaction
+/// independent. Parallel chunks read the block cache shared read-only and keep their own writes, which are merged back
+/// in transaction order.
+pub fn process_block_transactions<KVQ: KVQBinaryStoreImmutable, T: TxIndexChainAPI, W: TxIndexWorker<KVQ, T> + ?Sized>(
+  db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>,
+  q: Arc<T>,
+  ctx: &TxIndexBlockContext,
+) -> anyhow::Result<()> {
+  let contexts = ctx.get_transaction_contexts();
+  let threads = rayon::current_num_threads();
+  if !W::TRANSACTION_INDEPENDENT || threads < 2 || contexts.len() < 2 {
+    for ctx in contexts.iter() {
+      W::process_transaction(db, Arc::clone(&q), ctx)?;
+    }
+    return Ok(());
+  }
+
+  let chunk_size = (contexts.len() + threads - 1) / threads;
+  let base = Arc::new(std::mem::take(&mut db.store.map));
+  let chunks = contexts.par_chunks(chunk_size).map(|chunk| {
+    let mut store = KVQBinaryStoreCached::new_with_base(Arc::clone(&db.store.store), Arc::clone(&base));
+    store.proper_delete_return = db.store.proper_delete_return;
+    let mut chunk_db = IndexedBlockDBStore {
+      store,
+      block_number: db.block_number,
+      metadata: db.metadata,
+      actions: Vec::new(),
+    };
+    for ctx in chunk {
+      W::process_transaction(&mut chunk_db, Arc::clone(&q), ctx)?;
+    }
+    Ok((chunk_db.store.map, chunk_db.actions))
+  }).collect::<anyhow::Result<Vec<_>>>();
+
+  // every chunk cache is dropped by now, so the base is no longer shared
+  db.store.map = Arc::try_unwrap(base).unwrap_or_else(|base| base.as_ref().clone());
+  for (changes, actions) in chunks? {
+    db.store.map.extend(changes);
+    db.actions.extend(actions);
+  }
+  Ok(())
+}
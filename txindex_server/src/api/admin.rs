@@ -0,0 +1,127 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::warn;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+use super::core::HttpError;
+use super::rest::{full, Handle};
+use super::traits::BoxBody;
+
+/// A command sent by the admin API to the indexer loop, which runs it between index updates.
+pub enum TxIndexAdminCommand {
+    /// Roll the indexer db back to before `block_number` and index the rolled back blocks again. Replies with the
+    /// rolled back block numbers, newest first.
+    Rollback {
+        block_number: u64,
+        reply: oneshot::Sender<Result<Vec<u64>, String>>,
+    },
+}
+
+#[derive(Clone)]
+struct AdminServerHandler {
+    token: Arc<String>,
+    commands: mpsc::UnboundedSender<TxIndexAdminCommand>,
+}
+
+impl AdminServerHandler {
+    fn is_authorized(&self, req: &Request<Incoming>) -> bool {
+        let expected = format!("Bearer {}", self.token);
+        match req.headers().get(hyper::header::AUTHORIZATION) {
+            // compare every byte so the time taken does not depend on the length of the matching prefix
+            Some(value) => {
+                value.as_bytes().len() == expected.len()
+                    && value.as_bytes().iter().zip(expected.as_bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+            }
+            None => false,
+        }
+    }
+
+    async fn handle(&self, req: Request<Incoming>) -> Result<Response<BoxBody>, HttpError> {
+        if !self.is_authorized(&req) {
+            return Err(HttpError(StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
+        }
+        let path: Vec<&str> = req.uri().path().split('/').skip(1).collect();
+        match (req.method(), path.as_slice()) {
+            (&Method::POST, ["admin", "rollback", block_number]) => {
+                let block_number = block_number.parse::<u64>()?;
+                let (reply, result) = oneshot::channel();
+                self.commands
+                    .send(TxIndexAdminCommand::Rollback { block_number, reply })
+                    .map_err(|_| HttpError(StatusCode::SERVICE_UNAVAILABLE, "Indexer is not running".to_string()))?;
+                match result.await {
+                    Ok(Ok(rolled_back_blocks)) => Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/json")
+                        .body(full(json!({ "rolled_back_blocks": rolled_back_blocks }).to_string()))
+                        .unwrap()),
+                    Ok(Err(err)) => Err(HttpError(StatusCode::INTERNAL_SERVER_ERROR, err)),
+                    Err(_) => Err(HttpError(StatusCode::SERVICE_UNAVAILABLE, "Indexer is not running".to_string())),
+                }
+            }
+            _ => Err(HttpError::not_found("Admin endpoint not found".to_string())),
+        }
+    }
+}
+
+#[tokio::main]
+async fn run(addr: SocketAddr, handler: AdminServerHandler, rx: oneshot::Receiver<()>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Admin API listening on http://{}", addr);
+
+    let mut signal = std::pin::pin!(async {
+        rx.await.ok();
+    });
+
+    loop {
+        tokio::select! {
+            Ok((stream, _addr)) = listener.accept() => {
+                let io = TokioIo::new(stream);
+                let handler = handler.clone();
+
+                tokio::task::spawn(async move {
+                    let service = service_fn(|req| async {
+                        Ok::<_, hyper::Error>(handler.handle(req).await.unwrap_or_else(|err| {
+                            warn!("{:?}", err);
+                            Response::builder()
+                                .status(err.0)
+                                .header("Content-Type", "application/json")
+                                .body(full(err.to_json_bytes().unwrap()))
+                                .unwrap()
+                        }))
+                    });
+                    if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
+                        log::info!("Failed to serve admin connection: {:?}", err);
+                    }
+                });
+            },
+            _ = &mut signal => {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Starts the admin API on its own listener: `POST /admin/rollback/:height` with an `Authorization: Bearer <token>`
+/// header sends a rollback to `commands` and answers once the indexer ran it.
+pub fn start(addr: SocketAddr, token: String, commands: mpsc::UnboundedSender<TxIndexAdminCommand>) -> Handle {
+    let (tx, rx) = oneshot::channel::<()>();
+    let handler = AdminServerHandler {
+        token: Arc::new(token),
+        commands,
+    };
+
+    Handle {
+        tx,
+        thread: thread::spawn(move || run(addr, handler, rx).unwrap()),
+    }
+}
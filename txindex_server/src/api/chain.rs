@@ -2,11 +2,11 @@
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode;
 use hex::FromHex;
-use bitcoin::{address, hex::DisplayHex, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid};
+use bitcoin::{address, hashes::Hash, hex::DisplayHex, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid};
 use error_chain::bail;
 use hyper::{Method, Response, StatusCode};
 use log::info;
-use txindex_common::{chain::Network, config::Config, utils::{block::{BlockHeaderMeta, BlockId, DEFAULT_BLOCKHASH}, transaction::{extract_tx_prevouts, has_prevout, is_coinbase, TransactionStatus}, FullHash}};
+use txindex_common::{chain::{dogecoin_address_to_script, Network}, config::Config, db::{indexed_action::{IndexedBlockAction, IndexedBlockActionId}, indexed_block_db::IndexedBlockDBStoreReader, kvstore::BaseKVQStore}, utils::{block::{BlockHeaderMeta, BlockId, DEFAULT_BLOCKHASH}, transaction::{extract_tx_prevouts, has_prevout, is_coinbase, TransactionStatus}, FullHash}};
 
 use std::str::FromStr;
 
@@ -23,6 +23,7 @@ const CHAIN_TXS_PER_PAGE: usize = 25;
 const MAX_MEMPOOL_TXS: usize = 50;
 const BLOCK_LIMIT: usize = 10;
 const ADDRESS_SEARCH_LIMIT: usize = 10;
+const ACTIONS_PER_PAGE: usize = 100;
 
 
 const TTL_LONG: u32 = 157_784_630; // ttl for static resources (5 years)
@@ -91,6 +92,29 @@ impl BlockValue {
     }
 }
 
+#[derive(Serialize)]
+pub(crate) struct ActionValue {
+    block_number: u64,
+    worker_id: u32,
+    action_index: u32,
+    txid: Txid,
+    action_type: u32,
+    action_data: String,
+}
+
+impl From<IndexedBlockAction> for ActionValue {
+    fn from(action: IndexedBlockAction) -> Self {
+        ActionValue {
+            block_number: action.id.block_number,
+            worker_id: action.id.worker_id,
+            action_index: action.id.action_index,
+            txid: Txid::from_byte_array(action.action.txid),
+            action_type: action.action.action_type,
+            action_data: action.action.action_data.to_lower_hex_string(),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct TransactionValue {
     txid: Txid,
@@ -862,6 +886,25 @@ pub fn chain_handle_request(
             json_response(recent, TTL_MEMPOOL_RECENT)
         }
 
+        (&Method::GET, Some(&"actions"), Some(&"block"), Some(height), None, None) => {
+            let height = height.parse::<u64>()?;
+            let (start_after, limit) = parse_actions_page(&query_params)?;
+            let actions = actions_db(query).get_actions_by_block(height, start_after.as_ref(), limit)?;
+            actions_response(actions)
+        }
+        (&Method::GET, Some(&"actions"), Some(&"tx"), Some(hash), None, None) => {
+            let hash = Txid::from_str(hash)?;
+            let (start_after, limit) = parse_actions_page(&query_params)?;
+            let actions = actions_db(query).get_actions_by_txid(&hash.to_byte_array(), start_after.as_ref(), limit)?;
+            actions_response(actions)
+        }
+        (&Method::GET, Some(&"actions"), Some(&"worker"), Some(worker_id), None, None) => {
+            let worker_id = worker_id.parse::<u32>()?;
+            let (start_after, limit) = parse_actions_page(&query_params)?;
+            let actions = actions_db(query).get_actions_by_worker(worker_id, start_after.as_ref(), limit)?;
+            actions_response(actions)
+        }
+
         (&Method::GET, Some(&"fee-estimates"), None, None, None, None) => {
             json_response(query.estimate_fee_map(), TTL_SHORT)
         }
@@ -905,6 +948,43 @@ fn json_response<T: Serialize>(value: T, ttl: u32) -> Result<Response<BoxBody>,
         .unwrap())
 }
 
+fn actions_db(query: &Query) -> IndexedBlockDBStoreReader<BaseKVQStore> {
+    IndexedBlockDBStoreReader::new(query.get_kvq_db(), None)
+}
+
+// `start_after` is the `<block_number>:<worker_id>:<action_index>` of the last action of the previous page
+fn parse_actions_page(
+    query_params: &HashMap<String, String>,
+) -> Result<(Option<IndexedBlockActionId>, usize), HttpError> {
+    let start_after = match query_params.get("start_after") {
+        Some(cursor) => {
+            let parts: Vec<&str> = cursor.split(':').collect();
+            if parts.len() != 3 {
+                bail!(HttpError::from("Invalid start_after".to_string()));
+            }
+            Some(IndexedBlockActionId::new(
+                parts[0].parse::<u64>()?,
+                parts[1].parse::<u32>()?,
+                parts[2].parse::<u32>()?,
+            ))
+        }
+        None => None,
+    };
+    let limit = match query_params.get("limit") {
+        Some(limit) => limit.parse::<usize>()?.min(ACTIONS_PER_PAGE),
+        None => ACTIONS_PER_PAGE,
+    };
+    Ok((start_after, limit))
+}
+
+// the indexer may lag behind the chain tip, so action lists are never cached for long
+fn actions_response(actions: Vec<IndexedBlockAction>) -> Result<Response<BoxBody>, HttpError> {
+    json_response(
+        actions.into_iter().map(ActionValue::from).collect::<Vec<_>>(),
+        TTL_SHORT,
+    )
+}
+
 fn blocks(query: &Query, start_height: Option<usize>) -> Result<Response<BoxBody>, HttpError> {
     let mut values = Vec::new();
     let mut current_hash = match start_height {
@@ -954,6 +1034,11 @@ pub fn to_scripthash(
 }
 
 pub fn address_to_scripthash(addr: &str, network: Network) -> Result<FullHash, HttpError> {
+    if network.is_dogecoin() {
+        let script = dogecoin_address_to_script(addr, network)?;
+        return Ok(compute_script_hash(&script));
+    }
+
     #[cfg(not(feature = "liquid"))]
     let addr = address::Address::from_str(addr)?;
     #[cfg(feature = "liquid")]
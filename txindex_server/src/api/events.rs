@@ -0,0 +1,172 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use bitcoin::BlockHash;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use hyper::{Response, StatusCode};
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc};
+use txindex_common::db::indexed_action::IndexedBlockAction;
+
+use super::chain::ActionValue;
+use super::core::HttpError;
+use super::traits::BoxBody;
+
+/// Number of events kept for slow subscribers before they start missing events.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum TxIndexEvent {
+    /// Actions emitted while indexing a block.
+    Actions {
+        block_number: u64,
+        block_hash: BlockHash,
+        actions: Vec<IndexedBlockAction>,
+    },
+    /// Blocks undone by a reorg, newest first. Their actions were removed from the index.
+    Rollback { block_numbers: Vec<u64> },
+    /// The indexer caught up with a new chain tip.
+    Tip { block_number: u64, block_hash: BlockHash },
+}
+
+/// Publishes indexer events to the `/events` subscribers. Publishing without subscribers is a no-op.
+#[derive(Clone)]
+pub struct TxIndexEventBus {
+    sender: broadcast::Sender<Arc<TxIndexEvent>>,
+}
+
+impl TxIndexEventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+        TxIndexEventBus { sender }
+    }
+
+    pub fn publish(&self, event: TxIndexEvent) {
+        // only fails if nobody is subscribed
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<TxIndexEvent>> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for TxIndexEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Filters `/events?worker_id=1,2&action_type=3` subscriptions. Rollback and tip events are always sent.
+struct TxIndexEventFilter {
+    worker_ids: Option<HashSet<u32>>,
+    action_types: Option<HashSet<u32>>,
+}
+
+impl TxIndexEventFilter {
+    fn from_query_params(query_params: &HashMap<String, String>) -> Result<Self, HttpError> {
+        Ok(TxIndexEventFilter {
+            worker_ids: parse_id_list(query_params.get("worker_id"))?,
+            action_types: parse_id_list(query_params.get("action_type"))?,
+        })
+    }
+
+    fn matches(&self, action: &IndexedBlockAction) -> bool {
+        self.worker_ids.as_ref().map_or(true, |ids| ids.contains(&action.action.worker_id))
+            && self.action_types.as_ref().map_or(true, |types| types.contains(&action.action.action_type))
+    }
+
+    // returns `None` if nothing in the event matches
+    fn to_sse_message(&self, event: &TxIndexEvent) -> Option<String> {
+        let (name, data) = match event {
+            TxIndexEvent::Actions {
+                block_number,
+                block_hash,
+                actions,
+            } => {
+                let actions: Vec<ActionValue> = actions
+                    .iter()
+                    .filter(|action| self.matches(action))
+                    .cloned()
+                    .map(ActionValue::from)
+                    .collect();
+                if actions.is_empty() {
+                    return None;
+                }
+                (
+                    "actions",
+                    json!({ "block_number": block_number, "block_hash": block_hash, "actions": actions }),
+                )
+            }
+            TxIndexEvent::Rollback { block_numbers } => ("rollback", json!({ "block_numbers": block_numbers })),
+            TxIndexEvent::Tip {
+                block_number,
+                block_hash,
+            } => (
+                "tip",
+                json!({ "block_number": block_number, "block_hash": block_hash }),
+            ),
+        };
+        Some(format!("event: {}\ndata: {}\n\n", name, data))
+    }
+}
+
+fn parse_id_list(value: Option<&String>) -> Result<Option<HashSet<u32>>, HttpError> {
+    match value {
+        Some(value) => Ok(Some(
+            value
+                .split(',')
+                .map(|id| id.parse::<u32>())
+                .collect::<Result<HashSet<u32>, _>>()?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Streams indexer events as Server-Sent Events. A subscriber that falls more than `EVENT_BUFFER_SIZE` events
+/// behind gets a `lagged` event with the number of missed events and should catch up with the `/actions/` endpoints.
+pub fn events_handle_request(
+    uri: &hyper::Uri,
+    events: &TxIndexEventBus,
+) -> Result<Response<BoxBody>, HttpError> {
+    let query_params = match uri.query() {
+        Some(value) => url::form_urlencoded::parse(value.as_bytes())
+            .into_owned()
+            .collect::<HashMap<String, String>>(),
+        None => HashMap::new(),
+    };
+    let filter = TxIndexEventFilter::from_query_params(&query_params)?;
+    let mut receiver = events.subscribe();
+    let (tx, mut rx) = mpsc::channel::<Bytes>(16);
+
+    tokio::task::spawn(async move {
+        loop {
+            let message = match receiver.recv().await {
+                Ok(event) => match filter.to_sse_message(&event) {
+                    Some(message) => message,
+                    None => continue,
+                },
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    format!("event: lagged\ndata: {}\n\n", json!({ "missed_events": missed }))
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if tx.send(Bytes::from(message)).await.is_err() {
+                // the client disconnected
+                break;
+            }
+        }
+    });
+
+    let stream = futures::stream::poll_fn(move |cx| {
+        rx.poll_recv(cx).map(|chunk| chunk.map(|chunk| Ok::<_, hyper::Error>(Frame::data(chunk))))
+    });
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(StreamBody::new(stream).boxed())
+        .unwrap())
+}
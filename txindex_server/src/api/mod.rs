@@ -7,6 +7,9 @@ pub mod rest;
 pub mod traits;
 pub mod core;
 pub mod chain;
+pub mod module;
+pub mod events;
+pub mod admin;
 
 
 pub trait TxIndexAPIResponseHelper {
@@ -0,0 +1,39 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use hyper::{Method, Response};
+use txindex_common::{config::Config, db::{indexed_block_db::IndexedBlockDBStoreReader, kvstore::BaseKVQStore}, module::set::TxIndexModuleSet};
+
+use crate::daemon::{query::Query, schema::ChainQuery};
+
+use super::{core::HttpError, traits::{BoxBody, TxIndexRESTHandler}, TxIndexAPIResponseHelper};
+
+/// Routes `/indexer/` requests to the API handler of the matching module.
+pub struct TxIndexModuleSetRESTHandler<M> {
+  _modules: PhantomData<fn() -> M>,
+}
+
+impl<M> Clone for TxIndexModuleSetRESTHandler<M> {
+  fn clone(&self) -> Self {
+    Self { _modules: PhantomData }
+  }
+}
+
+impl<M: TxIndexModuleSet<BaseKVQStore, ChainQuery>> TxIndexRESTHandler for TxIndexModuleSetRESTHandler<M> {
+  fn handle_request(
+    _method: Method,
+    uri: hyper::Uri,
+    _body: hyper::body::Bytes,
+    q: Arc<Query>,
+    config: Arc<Config>,
+  ) -> Result<Response<BoxBody>, HttpError> {
+    M::handle_get_request(
+      config.network_type,
+      uri.path().to_string(),
+      uri.query().unwrap_or("").to_string(),
+      q.get_chain_query(),
+      IndexedBlockDBStoreReader::new(q.get_kvq_db(), q.mempool().pending_indexer_db()),
+    )
+    .map(|r| r.into_response())
+    .ok_or_else(|| HttpError::not_found("not found".to_string()))
+  }
+}
@@ -23,6 +23,7 @@ use crate::api::core::HttpError;
 use crate::daemon::query::Query;
 
 use super::chain::chain_handle_request;
+use super::events::events_handle_request;
 use super::traits::TxIndexRESTHandler;
 
 
@@ -54,6 +55,17 @@ pub async fn handle(
     let body = req.collect().await?.to_bytes();
     let query = Arc::clone(&self.query);
     let config = Arc::clone(&self.config);
+    if method == Method::GET && uri.path() == "/events" {
+        return Ok(events_handle_request(&uri, query.events()).unwrap_or_else(|err| {
+            warn!("{:?}", err);
+            Response::builder()
+                .status(err.0)
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Content-Type", "application/json")
+                .body(full(err.to_json_bytes().unwrap()))
+                .unwrap()
+        }));
+    }
     if uri.path().starts_with("/indexer/") {
 
 
@@ -160,8 +172,8 @@ pub async fn run<API: 'static + TxIndexRESTHandler + Clone + Send + Sync>(config
 
 }
 pub struct Handle {
-    tx: oneshot::Sender<()>,
-    thread: thread::JoinHandle<()>,
+    pub(crate) tx: oneshot::Sender<()>,
+    pub(crate) thread: thread::JoinHandle<()>,
 }
 
 impl Handle {
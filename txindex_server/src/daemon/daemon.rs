@@ -22,7 +22,7 @@ use txindex_common::daemon::cookie::CookieGetter;
 use url::Url;
 use crate::utils::metrics::{HistogramOpts, HistogramVec, Metrics};
 use crate::utils::signal::Waiter;
-use txindex_common::utils::block::{HeaderList, DEFAULT_BLOCKHASH};
+use txindex_common::utils::block::{deserialize_block, HeaderList, DEFAULT_BLOCKHASH};
 
 use txindex_errors::core::*;
 
@@ -62,10 +62,11 @@ fn header_from_value(value: Value) -> Result<BlockHeader> {
     )
 }
 
-fn block_from_value(value: Value) -> Result<Block> {
+fn block_from_value(value: Value, network: Network) -> Result<Block> {
     let block_hex = value.as_str().chain_err(|| "non-string block")?;
     let block_bytes = Vec::from_hex(block_hex).chain_err(|| "non-hex block")?;
-    Ok(deserialize(&block_bytes).chain_err(|| format!("failed to parse block {}", block_hex))?)
+    deserialize_block(&block_bytes, network)
+        .chain_err(|| format!("failed to parse block {}", block_hex))
 }
 
 fn tx_from_value(value: Value) -> Result<Transaction> {
@@ -392,6 +393,10 @@ impl Daemon {
         self.network.magic()
     }
 
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
     fn call_jsonrpc(&self, method: &str, request: &Value) -> Result<Value> {
         let mut conn = self.conn.lock().unwrap();
         let timer = self.latency.with_label_values(&[method]).start_timer();
@@ -492,7 +497,10 @@ impl Daemon {
 
     pub fn getblock(&self, blockhash: &BlockHash) -> Result<Block> {
         let block =
-            block_from_value(self.request("getblock", json!([blockhash, /*verbose=*/ false]))?)?;
+            block_from_value(
+            self.request("getblock", json!([blockhash, /*verbose=*/ false]))?,
+            self.network,
+        )?;
         assert_eq!(block.block_hash(), *blockhash);
         Ok(block)
     }
@@ -509,7 +517,7 @@ impl Daemon {
         let values = self.requests("getblock", &params_list)?;
         let mut blocks = vec![];
         for value in values {
-            blocks.push(block_from_value(value)?);
+            blocks.push(block_from_value(value, self.network)?);
         }
         Ok(blocks)
     }
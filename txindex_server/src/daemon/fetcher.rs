@@ -1,10 +1,11 @@
 use bitcoin::BlockHash;
 use log::{debug, trace};
 use rayon::prelude::*;
-use txindex_common::utils::block::{BlockEntry, HeaderEntry, SizedBlock};
+use txindex_common::chain::Network;
+use txindex_common::utils::block::{deserialize_block, BlockEntry, HeaderEntry, SizedBlock};
 use txindex_common::utils::{spawn_thread, SyncChannel};
 
-use bitcoin::consensus::encode::{deserialize, Decodable};
+use bitcoin::consensus::encode::Decodable;
 
 use std::collections::HashMap;
 use std::fs;
@@ -96,7 +97,7 @@ fn blkfiles_fetcher(
     daemon: &Daemon,
     new_headers: Vec<HeaderEntry>,
 ) -> Result<Fetcher<Vec<BlockEntry>>> {
-    let magic = daemon.magic();
+    let network = daemon.network();
     let blk_files = daemon.list_blk_files()?;
 
     let chan = SyncChannel::new(1);
@@ -104,7 +105,7 @@ fn blkfiles_fetcher(
     let mut entry_map: HashMap<BlockHash, HeaderEntry> =
         new_headers.into_iter().map(|h| (*h.hash(), h)).collect();
 
-    let parser = blkfiles_parser(blkfiles_reader(blk_files), magic);
+    let parser = blkfiles_parser(blkfiles_reader(blk_files), network);
     Ok(Fetcher::from(
         chan.into_receiver(),
         spawn_thread("blkfiles_fetcher", move || {
@@ -156,7 +157,7 @@ fn blkfiles_reader(blk_files: Vec<PathBuf>) -> Fetcher<Vec<u8>> {
     )
 }
 
-fn blkfiles_parser(blobs: Fetcher<Vec<u8>>, magic: u32) -> Fetcher<Vec<SizedBlock>> {
+fn blkfiles_parser(blobs: Fetcher<Vec<u8>>, network: Network) -> Fetcher<Vec<SizedBlock>> {
     let chan = SyncChannel::new(1);
     let sender = chan.sender();
 
@@ -165,7 +166,7 @@ fn blkfiles_parser(blobs: Fetcher<Vec<u8>>, magic: u32) -> Fetcher<Vec<SizedBloc
         spawn_thread("blkfiles_parser", move || {
             blobs.map(|blob| {
                 trace!("parsing {} bytes", blob.len());
-                let blocks = parse_blocks(blob, magic).expect("failed to parse blk*.dat file");
+                let blocks = parse_blocks(blob, network).expect("failed to parse blk*.dat file");
                 sender
                     .send(blocks)
                     .expect("failed to send blocks from blk*.dat file");
@@ -174,7 +175,8 @@ fn blkfiles_parser(blobs: Fetcher<Vec<u8>>, magic: u32) -> Fetcher<Vec<SizedBloc
     )
 }
 
-fn parse_blocks(blob: Vec<u8>, magic: u32) -> Result<Vec<SizedBlock>> {
+fn parse_blocks(blob: Vec<u8>, network: Network) -> Result<Vec<SizedBlock>> {
+    let magic = network.magic();
     let mut cursor = Cursor::new(&blob);
     let mut slices = vec![];
     let max_pos = blob.len() as u64;
@@ -207,8 +209,12 @@ fn parse_blocks(blob: Vec<u8>, magic: u32) -> Result<Vec<SizedBlock>> {
             }
             Err(_) => break, // EOF
         }
-        slices.push((&blob[start as usize..end as usize], block_size));
-        cursor.set_position(end as u64);
+        if end > max_pos {
+            trace!("skipping truncated block at offset {}", offset);
+            break;
+        }
+        slices.push((&blob[start as usize..end as usize], block_size, offset));
+        cursor.set_position(end);
     }
 
     let pool = rayon::ThreadPoolBuilder::new()
@@ -216,10 +222,14 @@ fn parse_blocks(blob: Vec<u8>, magic: u32) -> Result<Vec<SizedBlock>> {
         .thread_name(|i| format!("parse-blocks-{}", i))
         .build()
         .unwrap();
-    Ok(pool.install(|| {
+    pool.install(|| {
         slices
             .into_par_iter()
-            .map(|(slice, size)| (deserialize(slice).expect("failed to parse Block"), size))
+            .map(|(slice, size, offset)| {
+                let block = deserialize_block(slice, network)
+                    .chain_err(|| format!("failed to parse block at offset {}", offset))?;
+                Ok((block, size))
+            })
             .collect()
-    }))
+    })
 }
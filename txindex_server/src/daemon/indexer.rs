@@ -1,12 +1,12 @@
-use std::{collections::{BTreeSet, HashMap}, sync::Arc};
+use std::{borrow::Cow, collections::{BTreeSet, HashMap}, sync::Arc};
 
 use bitcoin::{BlockHash, OutPoint, Transaction, TxOut, Txid};
 use kvq::{base_types::{DBFlush, DBRow}, cache::KVQBinaryStoreCached};
-use log::{debug, info};
+use log::{debug, info, warn};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use txindex_common::{chain::Network, config::Config, db::{chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStore, kvstore::{BaseCDBStore, BaseKVQStore, TxIndexStore}}, utils::{block::{BlockEntry, BlockMeta, HeaderEntry}, full_hash, transaction::{has_prevout, is_spendable}}, worker::traits::TxIndexWorker};
+use txindex_common::{chain::Network, config::Config, db::{chain::TxIndexChainAPI, indexed_block_db::IndexedBlockDBStore, kvstore::{BaseCDBStore, BaseKVQStore, TxIndexStore}, prune::{prune_undo_history, IndexedBlockPruneConfig}}, utils::{block::{BlockEntry, BlockMeta, HeaderEntry}, full_hash, transaction::{has_prevout, is_spendable}}, worker::{context::TxIndexBlockContext, traits::TxIndexWorker}};
 
-use crate::{daemon::fetcher::start_fetcher, db::IndexForkHelper, utils::metrics::{Gauge, HistogramOpts, HistogramTimer, HistogramVec, MetricOpts, Metrics}};
+use crate::{api::events::{TxIndexEvent, TxIndexEventBus}, daemon::fetcher::start_fetcher, db::IndexForkHelper, utils::metrics::{Gauge, Histogram, HistogramOpts, HistogramTimer, HistogramVec, MetricOpts, Metrics}};
 
 use super::{daemon::Daemon, fetcher::FetchFrom, schema::{addr_search_row, BlockRow, FundingInfo, GetAmountVal, SpendingInfo, TxConfRow, TxEdgeRow, TxHistoryInfo, TxHistoryRow, TxOutRow, TxRow}};
 use bitcoin::consensus::encode::{deserialize, serialize};
@@ -24,6 +24,9 @@ pub struct Indexer {
   iconfig: IndexerConfig,
   duration: HistogramVec,
   tip_metric: Gauge,
+  reorg_depth: Histogram,
+  events: TxIndexEventBus,
+  prune: Option<IndexedBlockPruneConfig>,
 }
 
 pub struct IndexerConfig {
@@ -35,7 +38,7 @@ pub struct IndexerConfig {
 
 // TODO: &[Block] should be an iterator / a queue.
 impl Indexer {
-  pub fn open(store: Arc<TxIndexStore>, from: FetchFrom, config: &Config, metrics: &Metrics) -> Self {
+  pub fn open(store: Arc<TxIndexStore>, from: FetchFrom, config: &Config, metrics: &Metrics, events: TxIndexEventBus) -> Self {
       Indexer {
           store,
           flush: DBFlush::Disable,
@@ -46,6 +49,14 @@ impl Indexer {
               &["step"],
           ),
           tip_metric: metrics.gauge(MetricOpts::new("tip_height", "Current chain tip height")),
+          reorg_depth: metrics.histogram(
+              HistogramOpts::new("reorg_depth", "Number of indexed blocks rolled back by a reorg")
+                  .buckets(vec![1.0, 2.0, 3.0, 6.0, 10.0, 20.0, 50.0, 100.0]),
+          ),
+          events,
+          prune: config
+              .undo_horizon
+              .map(|undo_horizon| IndexedBlockPruneConfig::new(undo_horizon, &config.full_history_tables)),
       }
   }
 
@@ -134,9 +145,115 @@ impl Indexer {
       }
 
       self.tip_metric.set(headers.len() as i64 - 1);
+      self.events.publish(TxIndexEvent::Tip {
+          block_number: headers.len() as u64 - 1,
+          block_hash: tip,
+      });
       Ok(tip)
   }
 
+  /// Reconciles the indexer db with the headers loaded from the txstore before the first update: blocks indexed on an
+  /// orphaned branch or ahead of the txstore tip are rolled back, modules that are behind are caught up to the latest
+  /// indexed block, and blocks already marked as indexed in the history db but missing from the indexer db (which
+  /// `update` would skip) are indexed again.
+  pub fn reconcile<I: TxIndexWorker<BaseKVQStore, Q>, Q: TxIndexChainAPI>(&self, q: Arc<Q>) -> Result<()> {
+      let indexer_db = &self.store.indexer_db;
+      let (orphaned_block, replay_to) = {
+          let headers = self.store.indexed_headers.read().unwrap();
+          let indexed_blockhashes = self.store.indexed_blockhashes.read().unwrap();
+          let orphaned_block = IndexForkHelper::<Q, I>::find_orphaned_block(indexer_db, |block_number, block_hash| {
+              headers
+                  .header_by_height(block_number as usize)
+                  .map_or(false, |entry| entry.hash()[..] == block_hash[..])
+          })
+          .map_err(|e| Error::from(format!("failed to read the indexer db: {}", e)))?;
+          let replay_to = headers
+              .iter()
+              .rev()
+              .find(|entry| indexed_blockhashes.contains(entry.hash()))
+              .map(|entry| entry.height() as u64);
+          (orphaned_block, replay_to)
+      };
+
+      if let Some(orphaned_block) = orphaned_block {
+          let rolled_back_blocks = IndexForkHelper::<Q, I>::rollback_to_block(Arc::clone(indexer_db), orphaned_block)
+              .map_err(|e| Error::from(format!("failed to roll back orphaned blocks: {}", e)))?;
+          warn!(
+              "indexer db was ahead of or forked from the txstore: rolled back {} blocks ({:?})",
+              rolled_back_blocks.len(),
+              rolled_back_blocks
+          );
+      }
+
+      I::catch_up(indexer_db, Arc::clone(&q))
+          .map_err(|e| Error::from(format!("failed to catch up the modules: {}", e)))?;
+
+      let replay_to = match replay_to {
+          Some(replay_to) => replay_to,
+          None => return Ok(()),
+      };
+      let replay_from = match IndexForkHelper::<Q, I>::get_latest_indexed_block(indexer_db)
+          .map_err(|e| Error::from(format!("failed to read the indexer db: {}", e)))?
+      {
+          Some(block_number) => block_number + 1,
+          None => 0,
+      };
+      if replay_from <= replay_to {
+          info!("indexer db is behind the history db: indexing blocks {} to {}", replay_from, replay_to);
+      }
+      for block_number in replay_from..=replay_to {
+          let block = q
+              .get_block(block_number)
+              .map_err(|e| Error::from(format!("failed to load block {}: {}", block_number, e)))?;
+          let ctx = TxIndexBlockContext::new_from_chain(q.as_ref(), block_number, &block)
+              .map_err(|e| Error::from(format!("failed to load the prevouts of block {}: {}", block_number, e)))?;
+          let ibdb = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(indexer_db)), block_number, &block);
+          let update = IndexForkHelper::<Q, I>::update_with_block(ibdb, Arc::clone(&q), &ctx)
+              .map_err(|e| Error::from(format!("failed to index block {}: {}", block_number, e)))?;
+          if !update.actions.is_empty() {
+              self.events.publish(TxIndexEvent::Actions {
+                  block_number,
+                  block_hash: block.block_hash(),
+                  actions: update.actions,
+              });
+          }
+      }
+      Ok(())
+  }
+
+  /// Rolls the indexer db back to before `block_number`, then indexes that block and the following ones again, e.g.
+  /// to recompute the state of a module after fixing it. Returns the rolled back block numbers, newest first.
+  pub fn rollback_and_reindex<I: TxIndexWorker<BaseKVQStore, Q>, Q: TxIndexChainAPI>(&self, q: Arc<Q>, block_number: u64) -> Result<Vec<u64>> {
+      let rolled_back_blocks = IndexForkHelper::<Q, I>::rollback_to_block(Arc::clone(&self.store.indexer_db), block_number)
+          .map_err(|e| Error::from(format!("failed to roll back to block {}: {}", block_number, e)))?;
+      warn!(
+          "rolled back {} blocks to block {} ({:?})",
+          rolled_back_blocks.len(),
+          block_number,
+          rolled_back_blocks
+      );
+      if !rolled_back_blocks.is_empty() {
+          self.events.publish(TxIndexEvent::Rollback { block_numbers: rolled_back_blocks.clone() });
+      }
+      self.reconcile::<I, Q>(q)?;
+      Ok(rolled_back_blocks)
+  }
+
+  /// Prunes the undo history older than the configured undo horizon, if any.
+  pub fn prune_undo_history(&self) -> Result<()> {
+      let prune = match &self.prune {
+          Some(prune) => prune,
+          None => return Ok(()),
+      };
+      let _timer = self.start_timer("prune");
+      let pruned = prune_undo_history(self.store.indexer_db.as_ref(), prune)
+          .map_err(|e| Error::from(format!("failed to prune the undo history: {}", e)))?;
+      if pruned > 0 {
+          debug!("pruned the undo records of {} blocks", pruned);
+      }
+      Ok(())
+  }
+
   fn add(&self, blocks: &[BlockEntry]) {
       // TODO: skip orphaned blocks?
       let rows = {
@@ -177,7 +294,25 @@ impl Indexer {
       rows.into_iter().zip(blocks).for_each(|(r, b)|{
         let ibdb = IndexedBlockDBStore::new_from_block(KVQBinaryStoreCached::new(Arc::clone(&self.store.indexer_db)), b.entry.height() as u64, &b.block);
 
-        IndexForkHelper::<Q, I>::update_with_block(ibdb, Arc::clone(&q), b.entry.height() as u64, &b.block).unwrap();
+        let ctx = TxIndexBlockContext::new(b.entry.height() as u64, &b.block, Cow::Borrowed(&previous_txos_map));
+        let update = IndexForkHelper::<Q, I>::update_with_block(ibdb, Arc::clone(&q), &ctx).unwrap();
+        if !update.rolled_back_blocks.is_empty() {
+          warn!(
+            "reorg at block {}: rolled back {} blocks ({:?})",
+            ctx.block_number,
+            update.rolled_back_blocks.len(),
+            update.rolled_back_blocks
+          );
+          self.reorg_depth.observe(update.rolled_back_blocks.len() as f64);
+          self.events.publish(TxIndexEvent::Rollback { block_numbers: update.rolled_back_blocks });
+        }
+        if !update.actions.is_empty() {
+          self.events.publish(TxIndexEvent::Actions {
+            block_number: ctx.block_number,
+            block_hash: *b.entry.hash(),
+            actions: update.actions,
+          });
+        }
         
         self.store.history_db.write(r, self.flush);
 
@@ -1,10 +1,11 @@
 use arraydeque::{ArrayDeque, Wrapping};
-use bitcoin::{consensus::encode::{deserialize, serialize}, OutPoint, Transaction, TxOut, Txid};
+use bitcoin::{consensus::encode::{deserialize, serialize}, BlockHash, OutPoint, Transaction, TxOut, Txid};
 use itertools::Itertools;
 use log::warn;
 use prometheus::{GaugeVec, HistogramOpts, HistogramVec};
 use serde::Serialize;
-use txindex_common::{chain::Network, utils::{full_hash, transaction::{extract_tx_prevouts, has_prevout, is_spendable}}};
+use kvq::cache::KVQBinaryStoreCached;
+use txindex_common::{chain::Network, db::{indexed_block::IndexedBlockMetadata, indexed_block_db::IndexedBlockDBStore, kvstore::BaseKVQStore}, utils::{full_hash, transaction::{extract_tx_prevouts, has_prevout, is_spendable}}, worker::mempool::{TxIndexMempoolTransactionContext, TxIndexMempoolWorker}};
 use txindex_common::config::Config;
 use txindex_common::utils::FullHash;
 
@@ -31,6 +32,7 @@ pub struct Mempool {
     edges: HashMap<OutPoint, (Txid, u32)>,          // OutPoint -> (spending_txid, spending_vin)
     recent: ArrayDeque<TxOverview, RECENT_TXS_SIZE, Wrapping>, // The N most recent txs to enter the mempool
     backlog_stats: (BacklogStats, Instant),
+    pending_index: Option<PendingIndex>,
 
     // monitoring
     latency: HistogramVec, // mempool requests latency
@@ -44,6 +46,25 @@ pub struct Mempool {
     pub asset_issuance: HashMap<AssetId, asset::AssetRow>,
 }
 
+// The mempool transactions indexed by the TxIndexMempoolWorker on top of the confirmed indexer db at `tip`
+struct PendingIndex {
+    tip: BlockHash,
+    txids: HashSet<Txid>,
+    db: Arc<KVQBinaryStoreCached<BaseKVQStore>>,
+}
+
+impl PendingIndex {
+    // The overlay, `None` if it was built on another tip: its writes are at the block number of the block indexed
+    // since, so they would hide the confirmed values and count the mined transactions twice.
+    fn db_at_tip(&self, tip: &BlockHash) -> Option<Arc<KVQBinaryStoreCached<BaseKVQStore>>> {
+        if self.tip == *tip {
+            Some(Arc::clone(&self.db))
+        } else {
+            None
+        }
+    }
+}
+
 // A simplified transaction view used for the list of most recent transactions
 #[derive(Serialize)]
 pub struct TxOverview {
@@ -68,6 +89,7 @@ impl Mempool {
                 BacklogStats::default(),
                 Instant::now() - Duration::from_secs(BACKLOG_STATS_TTL),
             ),
+            pending_index: None,
             latency: metrics.histogram_vec(
                 HistogramOpts::new("mempool_latency", "Mempool requests latency (in seconds)"),
                 &["part"],
@@ -473,6 +495,136 @@ impl Mempool {
             .retain(|_outpoint, (txid, _vin)| !to_remove.contains(txid));
     }
 
+    /// The indexer db with the mempool transactions applied, `None` until a `TxIndexMempoolWorker` built it on the
+    /// current tip.
+    pub fn pending_indexer_db(&self) -> Option<Arc<KVQBinaryStoreCached<BaseKVQStore>>> {
+        let tip = self.chain.best_hash();
+        self.pending_index.as_ref().and_then(|pending| pending.db_at_tip(&tip))
+    }
+
+    // Mempool txids that are not in `skip`, parents first
+    fn pending_tx_order(&self, skip: &HashSet<Txid>) -> Vec<Txid> {
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for txid in self.txstore.keys().sorted() {
+            self.visit_pending_tx(txid, skip, &mut visited, &mut order);
+        }
+        order
+    }
+
+    fn visit_pending_tx(&self, txid: &Txid, skip: &HashSet<Txid>, visited: &mut HashSet<Txid>, order: &mut Vec<Txid>) {
+        if skip.contains(txid) || !visited.insert(*txid) {
+            return;
+        }
+        let tx = match self.txstore.get(txid) {
+            Some(tx) => tx,
+            None => return, // confirmed parent
+        };
+        for txin in tx.input.iter() {
+            self.visit_pending_tx(&txin.previous_output.txid, skip, visited, order);
+        }
+        order.push(*txid);
+    }
+
+    fn build_pending_index<W: TxIndexMempoolWorker<BaseKVQStore, ChainQuery>>(&self) -> Result<PendingIndex> {
+        let tip = self.chain.best_hash();
+        let block_number = self.chain.best_height() as u64 + 1;
+
+        // patch the current overlay if it was built on the same tip and none of its transactions left the mempool
+        let (store, mut txids) = match &self.pending_index {
+            Some(pending) if pending.tip == tip && pending.txids.iter().all(|txid| self.txstore.contains_key(txid)) => (
+                KVQBinaryStoreCached {
+                    store: Arc::clone(&pending.db.store),
+                    map: pending.db.map.clone(),
+                    base: pending.db.base.clone(),
+                    proper_delete_return: pending.db.proper_delete_return,
+                },
+                pending.txids.clone(),
+            ),
+            _ => (
+                KVQBinaryStoreCached::new(Arc::clone(&self.chain.store.indexer_db)),
+                HashSet::new(),
+            ),
+        };
+        let new_txids = self.pending_tx_order(&txids);
+
+        let outpoints: BTreeSet<OutPoint> = new_txids
+            .iter()
+            .flat_map(|txid| self.txstore[txid].input.iter())
+            .filter(|txin| has_prevout(txin))
+            .map(|txin| txin.previous_output)
+            .collect();
+        let confirmed_txos = self.chain.lookup_avail_txos(&outpoints);
+
+        let mut db = IndexedBlockDBStore {
+            store,
+            block_number,
+            metadata: IndexedBlockMetadata::new(block_number, 0, [0u8; 32]),
+            actions: Vec::new(),
+        };
+        for txid in new_txids {
+            let tx = &self.txstore[&txid];
+            let prevouts = tx
+                .input
+                .iter()
+                .map(|txin| {
+                    if !has_prevout(txin) {
+                        return None;
+                    }
+                    confirmed_txos.get(&txin.previous_output).cloned().or_else(|| {
+                        self.txstore
+                            .get(&txin.previous_output.txid)
+                            .and_then(|prev_tx| prev_tx.output.get(txin.previous_output.vout as usize).cloned())
+                    })
+                })
+                .collect();
+            let ctx = TxIndexMempoolTransactionContext {
+                block_number,
+                tx,
+                prevouts,
+                fee: self.feeinfo.get(&txid).map(|feeinfo| feeinfo.fee),
+            };
+            W::process_mempool_transaction(&mut db, Arc::clone(&self.chain), &ctx)
+                .map_err(|e| Error::from(format!("failed to index mempool tx {}: {}", txid, e)))?;
+            txids.insert(txid);
+        }
+
+        Ok(PendingIndex {
+            tip,
+            txids,
+            db: Arc::new(db.store),
+        })
+    }
+
+    /// Drops the pending overlay so the next `update_pending_index` rebuilds it, e.g. after blocks were indexed again.
+    pub fn clear_pending_index(&mut self) {
+        self.pending_index = None;
+    }
+
+    /// Runs `W` over the transactions that entered the mempool since the last call, rebuilding the pending overlay
+    /// from scratch if the tip changed or transactions were removed. The overlay is dropped if `W` fails.
+    pub fn update_pending_index<W: TxIndexMempoolWorker<BaseKVQStore, ChainQuery>>(mempool: &Arc<RwLock<Mempool>>) -> Result<()> {
+        if !W::ENABLED {
+            return Ok(());
+        }
+        let pending_index = {
+            let mempool = mempool.read().unwrap();
+            let _timer = mempool.latency.with_label_values(&["update_pending_index"]).start_timer();
+            mempool.build_pending_index::<W>()
+        };
+        let mut mempool = mempool.write().unwrap();
+        match pending_index {
+            Ok(pending_index) => {
+                mempool.pending_index = Some(pending_index);
+                Ok(())
+            }
+            Err(e) => {
+                mempool.pending_index = None;
+                Err(e)
+            }
+        }
+    }
+
     #[cfg(feature = "liquid")]
     pub fn asset_history(&self, asset_id: &AssetId, limit: usize) -> Vec<Transaction> {
         let _timer = self
@@ -558,3 +710,51 @@ impl BacklogStats {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{blockdata::constants::genesis_block, hashes::Hash};
+    use kvq::traits::{KVQBinaryStoreWriterImmutable, KVQSerializable, KVQWriteBatch};
+    use txindex_common::{
+        db::{
+            indexed_block_db::IndexedBlockDBStoreReader,
+            kvstore::{TxIndexKVQBackend, TxIndexKVQStore},
+            table::traits::get_real_key_at_block,
+        },
+        module::cursor::TxIndexModuleSyncCursor,
+    };
+
+    use super::*;
+
+    #[test]
+    fn overlays_built_on_an_older_tip_are_not_served() {
+        let store = Arc::new(TxIndexKVQStore::open(TxIndexKVQBackend::Memory, "unused").unwrap());
+        let old_tip = genesis_block(bitcoin::Network::Regtest).block_hash();
+        let new_tip = BlockHash::all_zeros();
+
+        // the mempool overlay writes a value at block 1, on top of the genesis tip
+        let mut db = IndexedBlockDBStore {
+            store: KVQBinaryStoreCached::new(Arc::clone(&store)),
+            block_number: 1,
+            metadata: IndexedBlockMetadata::new(1, 0, [0u8; 32]),
+            actions: Vec::new(),
+        };
+        db.put::<TxIndexModuleSyncCursor>(&7, &1).unwrap();
+        let pending = PendingIndex {
+            tip: old_tip,
+            txids: HashSet::new(),
+            db: Arc::new(db.store),
+        };
+        assert!(pending.db_at_tip(&old_tip).is_some());
+
+        // block 1 is indexed with another value for the key
+        let mut batch = KVQWriteBatch::new();
+        batch.set(get_real_key_at_block::<TxIndexModuleSyncCursor>(&7, 1).unwrap(), 2u64.to_bytes().unwrap());
+        store.imm_write_batch(batch).unwrap();
+
+        let stale = IndexedBlockDBStoreReader::new(Arc::clone(&store), Some(Arc::clone(&pending.db))).with_pending();
+        assert_eq!(stale.get::<TxIndexModuleSyncCursor>(&7).unwrap(), Some(1));
+        let reader = IndexedBlockDBStoreReader::new(Arc::clone(&store), pending.db_at_tip(&new_tip)).with_pending();
+        assert_eq!(reader.get::<TxIndexModuleSyncCursor>(&7).unwrap(), Some(2));
+    }
+}
@@ -11,6 +11,7 @@ use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::time::{Duration, Instant};
 
 use crate::utils::chain::{OutPoint, Transaction, TxOut, Txid};
+use crate::api::events::TxIndexEventBus;
 use super::daemon::Daemon;
 use super::mempool::Mempool;
 use super::schema::{ChainQuery, ScriptStats, SpendingInput, Utxo};
@@ -28,6 +29,7 @@ pub struct Query {
     mempool: Arc<RwLock<Mempool>>,
     daemon: Arc<Daemon>,
     config: Arc<Config>,
+    events: TxIndexEventBus,
     cached_estimates: RwLock<(HashMap<u16, f64>, Option<Instant>)>,
     cached_relayfee: RwLock<Option<f64>>,
 }
@@ -38,12 +40,14 @@ impl Query {
         mempool: Arc<RwLock<Mempool>>,
         daemon: Arc<Daemon>,
         config: Arc<Config>,
+        events: TxIndexEventBus,
     ) -> Self {
         Query {
             chain,
             mempool,
             daemon,
             config,
+            events,
             cached_estimates: RwLock::new((HashMap::new(), None)),
             cached_relayfee: RwLock::new(None),
         }
@@ -54,6 +58,9 @@ impl Query {
     pub fn get_chain_query(&self) -> Arc<ChainQuery> {
         Arc::clone(&self.chain)
     }
+    pub fn events(&self) -> &TxIndexEventBus {
+        &self.events
+    }
 
     pub fn chain(&self) -> &ChainQuery {
         &self.chain
@@ -1,8 +1,7 @@
 use std::{marker::PhantomData, sync::Arc};
 
-use bitcoin::Block;
 use kvq::cache::KVQBinaryStoreCached;
-use txindex_common::{db::{chain::TxIndexChainAPI, indexed_block::IndexedBlockFull, indexed_block_db::IndexedBlockDBStore, kvstore::BaseKVQStore, table::{core::KVQTableWrapper, traits::{get_real_key_at_block, KVQTableReaderAtBlock}}}, worker::traits::TxIndexWorker};
+use txindex_common::{db::{chain::TxIndexChainAPI, indexed_action::IndexedBlockAction, indexed_block::{IndexedBlockFull, IndexedBlockMetadata}, indexed_block_db::IndexedBlockDBStore, kvstore::BaseKVQStore, prune::get_pruned_until, table::{core::KVQTableWrapper, traits::KVQTableReaderAtBlock}}, worker::{context::TxIndexBlockContext, traits::TxIndexWorker}};
 use kvq::traits::KVQBinaryStoreWriterImmutable;
 
 pub struct IndexForkHelper<T: TxIndexChainAPI, I: TxIndexWorker<KVQ, T>> {
@@ -10,46 +9,112 @@ pub struct IndexForkHelper<T: TxIndexChainAPI, I: TxIndexWorker<KVQ, T>> {
     pub _t: PhantomData<T>,
 }
 
+/// What `update_with_block` changed, published to event subscribers.
+#[derive(Debug, Clone)]
+pub struct IndexForkUpdate {
+    /// Blocks that were undone before indexing the new block, newest first.
+    pub rolled_back_blocks: Vec<u64>,
+    pub actions: Vec<IndexedBlockAction>,
+}
+
 type KVQ = BaseKVQStore;
 impl<T: TxIndexChainAPI, I: TxIndexWorker<KVQ, T>> IndexForkHelper<T, I> {
   fn rollback_block(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, block: &IndexedBlockFull) -> anyhow::Result<()> {
-    let del_keys = block.added_fuzzy_block_keys.iter().chain(block.added_write_once_keys.iter()).chain(block.added_standard_keys.iter().map(|x|&x.key)).map(|key| key.to_vec()).collect::<Vec<Vec<u8>>>();
-    db.store.store.imm_delete_many(&del_keys)?;
-    block.modified_standard_keys.iter().map(|x| db.store.store.imm_set_ref(&x.key, &x.old_value)).collect::<anyhow::Result<()>>()?;
-
-    db.store.store.imm_delete(&get_real_key_at_block::<IndexedBlockFull>(&block.metadata.block_number, 0x1fffffffffffffff)?)?;
-    Ok(())
+    db.store.store.imm_write_batch(block.get_undo_batch()?)
   }
 
-  pub fn rollback_blocks(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, incoming_block_number: u64) -> anyhow::Result<u64> {
+  /// Rolls back every indexed block from `incoming_block_number` on, returning the next block to process and the
+  /// rolled back block numbers.
+  /// Fails if the undo records needed to reach `incoming_block_number` were pruned.
+  pub fn rollback_blocks(db: &mut IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, incoming_block_number: u64) -> anyhow::Result<(u64, Vec<u64>)> {
+    if let Some(pruned_until) = get_pruned_until(db.store.store.as_ref())? {
+      if incoming_block_number <= pruned_until {
+        anyhow::bail!("cannot roll back to block {}: undo history is pruned up to block {}", incoming_block_number, pruned_until);
+      }
+    }
+    let mut rolled_back_blocks = Vec::new();
     loop {
       let r = KVQTableWrapper::<IndexedBlockFull, KVQ>::get_leq_kv_at_block(&db.store.store, 0x1fffffffffffffff, &0x1fffffffffffffffu64, 8)?;
       if r.is_none() {
-        return Ok(0);
+        return Ok((0, rolled_back_blocks));
       }
       let r = r.unwrap();
       let last_block_number = r.key;
       if r.key < incoming_block_number {
-        return Ok(r.key+1);
+        return Ok((r.key+1, rolled_back_blocks));
       }
       Self::rollback_block(db, &r.value)?;
+      I::on_rollback(last_block_number, &r.value)?;
+      rolled_back_blocks.push(last_block_number);
       if last_block_number == incoming_block_number {
-        return Ok(last_block_number);
+        return Ok((last_block_number, rolled_back_blocks));
+      }
+    }
+  }
+  /// Returns the block number of the latest undo record, `None` if no block was indexed.
+  pub fn get_latest_indexed_block(db_store: &KVQ) -> anyhow::Result<Option<u64>> {
+    IndexedBlockFull::get_latest_block_number(db_store)
+  }
+
+  /// Walks the undo records down from the latest one until `is_on_chain(block_number, block_hash)` holds, returning the
+  /// oldest block that is not on chain (the first block to roll back), or `None` if the latest record is on chain.
+  /// Fails if the blocks that are not on chain reach below the pruned undo history.
+  pub fn find_orphaned_block(db_store: &KVQ, is_on_chain: impl Fn(u64, &[u8; 32]) -> bool) -> anyhow::Result<Option<u64>> {
+    let mut orphaned_block = None;
+    let mut search_block_number = 0x1fffffffffffffffu64;
+    while let Some(r) = KVQTableWrapper::<IndexedBlockFull, KVQ>::get_leq_kv_at_block(db_store, 0x1fffffffffffffff, &search_block_number, 8)? {
+      if is_on_chain(r.key, &r.value.metadata.block_hash) {
+        return Ok(orphaned_block);
       }
+      orphaned_block = Some(r.key);
+      if r.key == 0 {
+        return Ok(orphaned_block);
+      }
+      search_block_number = r.key - 1;
+    }
+    if let (Some(orphaned_block), Some(pruned_until)) = (orphaned_block, get_pruned_until(db_store)?) {
+      anyhow::bail!("indexed blocks from {} are not on chain, and undo history is pruned up to block {}", orphaned_block, pruned_until);
     }
+    Ok(orphaned_block)
   }
-  pub fn update_with_block(mut db: IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, block_number: u64, block: &Block) -> anyhow::Result<()>{
-    let ready_for_block_number = Self::rollback_blocks(&mut db, block_number)?;
+
+  /// Rolls back every indexed block from `block_number` on, returning the rolled back block numbers (newest first).
+  pub fn rollback_to_block(db_store: Arc<KVQ>, block_number: u64) -> anyhow::Result<Vec<u64>> {
+    let mut db = IndexedBlockDBStore {
+      store: KVQBinaryStoreCached::new(db_store),
+      block_number,
+      metadata: IndexedBlockMetadata::new(block_number, 0, [0u8; 32]),
+      actions: Vec::new(),
+    };
+    Ok(Self::rollback_blocks(&mut db, block_number)?.1)
+  }
+
+  /// Rolls back to the block of `ctx`, processes any missing blocks and the block itself, then records the block.
+  /// Workers built from a `TxIndexModuleSet` keep their own sync cursors, so only modules that are behind do any work.
+  pub fn update_with_block(mut db: IndexedBlockDBStore<KVQBinaryStoreCached<KVQ>>, q: Arc<T>, ctx: &TxIndexBlockContext) -> anyhow::Result<IndexForkUpdate>{
+    let block_number = ctx.block_number;
+    let (ready_for_block_number, rolled_back_blocks) = Self::rollback_blocks(&mut db, block_number)?;
     if ready_for_block_number != block_number && block_number != 0 {
         log::debug!("missing blocks from {} to {}", ready_for_block_number, block_number);
+      let db_block_number = db.block_number;
       for missing_block_num in ready_for_block_number..block_number {
+        if !I::is_active_at_block(missing_block_num) {
+          continue;
+        }
         log::debug!("processing block {}", missing_block_num);
         let block = q.get_block(missing_block_num)?;
-        I::process_block(&mut db, Arc::clone(&q), missing_block_num, &block)?;
+        db.block_number = missing_block_num;
+        I::process_block(&mut db, Arc::clone(&q), &TxIndexBlockContext::new_from_chain(q.as_ref(), missing_block_num, &block)?)?;
       }
+      db.block_number = db_block_number;
+    }
+    if I::is_active_at_block(block_number) {
+      I::process_block(&mut db, q, ctx)?;
     }
-    I::process_block(&mut db, q, block_number, block)?;
-    IndexedBlockFull::save_from_db_store(db)?;
-    Ok(())
+    let actions = IndexedBlockFull::save_from_db_store(db)?;
+    Ok(IndexForkUpdate {
+      rolled_back_blocks,
+      actions,
+    })
   }
 }
\ No newline at end of file
@@ -1,13 +1,13 @@
 use std::{process, sync::{Arc, RwLock}, time::Duration};
 
 use log::{debug, info, warn};
-use txindex_common::{config::Config, db::kvstore::{BaseCDBStore, BaseKVQStore, TxIndexStore}, utils::block::HeaderList, worker::traits::TxIndexWorker};
+use txindex_common::{config::Config, db::{indexed_block::IndexedBlockFull, kvstore::{BaseCDBStore, BaseKVQStore, TxIndexStore}}, module::set::{TxIndexModuleSet, TxIndexModuleSetWorker}, utils::block::HeaderList, worker::{mempool::TxIndexMempoolWorker, traits::TxIndexWorker}};
 use bitcoin::consensus::encode::deserialize;
 
-use crate::{api::traits::TxIndexRESTHandler, daemon::{daemon::Daemon, fetcher::FetchFrom, indexer::Indexer, mempool::Mempool, query::Query, schema::{load_blockhashes, load_blockheaders, BlockRow, ChainQuery}}, utils::{metrics::{MetricOpts, Metrics}, signal::Waiter}};
+use crate::{api::{admin::{self, TxIndexAdminCommand}, events::TxIndexEventBus, module::TxIndexModuleSetRESTHandler, traits::TxIndexRESTHandler}, daemon::{daemon::Daemon, fetcher::FetchFrom, indexer::Indexer, mempool::Mempool, query::Query, schema::{load_blockhashes, load_blockheaders, BlockRow, ChainQuery}}, utils::{metrics::{MetricOpts, Metrics}, signal::Waiter}};
 use crate::api::rest;
 use txindex_errors::core::*;
-use error_chain::ChainedError;
+use error_chain::{bail, ChainedError};
 
 fn fetch_from(config: &Config, store: &TxIndexStore) -> FetchFrom {
   let mut jsonrpc_import = config.jsonrpc_import;
@@ -50,10 +50,11 @@ pub fn open_tx_index_store(config: Arc<Config>) -> TxIndexStore {
       HeaderList::empty()
   };
 
-  let indexer_db =BaseKVQStore::open_default(path.join("indexer_db"));
-  if indexer_db.is_err() {
-    panic!("failed to open indexer_db");
+  let indexer_db = BaseKVQStore::open(config.indexer_backend, path.join("indexer_db"));
+  if let Err(e) = &indexer_db {
+    panic!("failed to open indexer_db: {}", e);
   }
+  debug!("indexer_db opened with the {:?} backend", config.indexer_backend);
   let indexer_db =  Arc::new(indexer_db.unwrap());
   TxIndexStore {
     txstore_db,
@@ -67,6 +68,18 @@ pub fn open_tx_index_store(config: Arc<Config>) -> TxIndexStore {
 
 }
 pub fn start_txindex_server_with_config<API: 'static + TxIndexRESTHandler + Clone + Send + Sync, I: TxIndexWorker<BaseKVQStore, ChainQuery>>(config: Arc<Config>) -> Result<()> {
+  start_txindex_server_with_mempool_with_config::<API, I, ()>(config)
+}
+
+/// Like `start_txindex_server_with_config`, and also indexes the mempool with `MW` after every mempool update.
+pub fn start_txindex_server_with_mempool_with_config<
+  API: 'static + TxIndexRESTHandler + Clone + Send + Sync,
+  I: TxIndexWorker<BaseKVQStore, ChainQuery>,
+  MW: TxIndexMempoolWorker<BaseKVQStore, ChainQuery>,
+>(config: Arc<Config>) -> Result<()> {
+  if config.admin_addr.is_some() && config.admin_token.is_none() {
+      bail!("--admin-addr requires --admin-token");
+  }
   let signal = Waiter::start();
   let metrics = Metrics::new(config.monitoring_addr);
   metrics.start();
@@ -81,11 +94,13 @@ pub fn start_txindex_server_with_config<API: 'static + TxIndexRESTHandler + Clon
       &metrics,
   )?);
   let store = Arc::new(open_tx_index_store(config.clone()));
+  let events = TxIndexEventBus::new();
   let mut indexer = Indexer::open(
       Arc::clone(&store),
       fetch_from(&Arc::clone(&config), &store),
       &config,
       &metrics,
+      events.clone(),
   );
 
   let chain = Arc::new(ChainQuery::new(
@@ -95,7 +110,22 @@ pub fn start_txindex_server_with_config<API: 'static + TxIndexRESTHandler + Clon
       &metrics,
   ));
 
+  if config.verify_db {
+      let verification = IndexedBlockFull::verify_all(store.indexer_db.as_ref())
+          .map_err(|e| Error::from(format!("failed to verify the indexer db: {}", e)))?;
+      for error in verification.errors.iter() {
+          warn!("{}", error);
+      }
+      if !verification.errors.is_empty() {
+          bail!("indexer db verification failed: {} problems in {} undo records", verification.errors.len(), verification.records);
+      }
+      info!("indexer db verified: {} undo records are consistent", verification.records);
+      return Ok(());
+  }
+  indexer.reconcile::<I, ChainQuery>(Arc::clone(&chain))?;
+
   let mut tip = indexer.update::<I, ChainQuery>(&daemon, Arc::clone(&chain))?;
+  indexer.prune_undo_history()?;
 
   let mempool = Arc::new(RwLock::new(Mempool::new(
       Arc::clone(&chain),
@@ -111,16 +141,25 @@ pub fn start_txindex_server_with_config<API: 'static + TxIndexRESTHandler + Clon
           },
       }
   }
+  if let Err(e) = Mempool::update_pending_index::<MW>(&mempool) {
+      warn!("Error indexing mempool: {}", e.display_chain());
+  }
 
   let query = Arc::new(Query::new(
       Arc::clone(&chain),
       Arc::clone(&mempool),
       Arc::clone(&daemon),
       Arc::clone(&config),
+      events,
   ));
 
   // TODO: configuration for which servers to start
   let rest_server = rest::start::<API>(Arc::clone(&config), Arc::clone(&query));
+  let (admin_commands, mut admin_command_receiver) = tokio::sync::mpsc::unbounded_channel::<TxIndexAdminCommand>();
+  let admin_server = match (config.admin_addr, &config.admin_token) {
+      (Some(admin_addr), Some(admin_token)) => Some(admin::start(admin_addr, admin_token.clone(), admin_commands)),
+      _ => None,
+  };
 
   let main_loop_count = metrics.gauge(MetricOpts::new(
       "electrs_main_loop_count",
@@ -134,6 +173,9 @@ pub fn start_txindex_server_with_config<API: 'static + TxIndexRESTHandler + Clon
       if let Err(err) = signal.wait(Duration::from_secs(5), true) {
           info!("stopping server: {}", err);
           rest_server.stop();
+          if let Some(admin_server) = admin_server {
+              admin_server.stop();
+          }
           // the electrum server is stopped when dropped
           break;
       }
@@ -142,13 +184,33 @@ pub fn start_txindex_server_with_config<API: 'static + TxIndexRESTHandler + Clon
       let current_tip = daemon.getbestblockhash()?;
       if current_tip != tip {
           indexer.update::<I, ChainQuery>(&daemon, Arc::clone(&chain))?;
+          // the overlay was built on the old tip, drop it even if the mempool update below fails
+          mempool.write().unwrap().clear_pending_index();
+          indexer.prune_undo_history()?;
           tip = current_tip;
       };
 
+      // Run admin commands
+      while let Ok(command) = admin_command_receiver.try_recv() {
+          match command {
+              TxIndexAdminCommand::Rollback { block_number, reply } => {
+                  let result = indexer.rollback_and_reindex::<I, ChainQuery>(Arc::clone(&chain), block_number);
+                  if let Err(e) = &result {
+                      warn!("Error running admin rollback: {}", e.display_chain());
+                  }
+                  mempool.write().unwrap().clear_pending_index();
+                  // the admin request may have timed out
+                  let _ = reply.send(result.map_err(|e| e.to_string()));
+              }
+          }
+      }
+
       // Update mempool
       if let Err(e) = Mempool::update(&mempool, &daemon) {
           // Log the error if the result is an Err
           warn!("Error updating mempool, skipping mempool update: {}", e.display_chain());
+      } else if let Err(e) = Mempool::update_pending_index::<MW>(&mempool) {
+          warn!("Error indexing mempool, pending state is unavailable: {}", e.display_chain());
       }
 
       // Update subscribed clients
@@ -165,3 +227,39 @@ pub fn start_txindex_server<API: 'static + TxIndexRESTHandler + Clone + Send + S
       process::exit(1);
   }
 }
+
+pub fn start_txindex_modules_server_with_config<M: 'static + TxIndexModuleSet<BaseKVQStore, ChainQuery>>(config: Arc<Config>) -> Result<()> {
+  start_txindex_modules_server_with_mempool_with_config::<M, ()>(config)
+}
+
+pub fn start_txindex_modules_server_with_mempool_with_config<
+  M: 'static + TxIndexModuleSet<BaseKVQStore, ChainQuery>,
+  MW: TxIndexMempoolWorker<BaseKVQStore, ChainQuery>,
+>(config: Arc<Config>) -> Result<()> {
+  if let Err(e) = M::validate_modules() {
+      bail!("invalid modules: {}", e);
+  }
+  if let Err(e) = M::validate_tables() {
+      bail!("invalid module tables: {}", e);
+  }
+  info!("starting txindex server with modules: {}", M::module_names().join(", "));
+  start_txindex_server_with_mempool_with_config::<TxIndexModuleSetRESTHandler<M>, TxIndexModuleSetWorker<M>, MW>(config)
+}
+
+/// Starts a server that indexes and serves every module of `M`, e.g. `start_txindex_modules_server::<(TxCounterModule<BaseKVQStore, ChainQuery>,)>()`.
+pub fn start_txindex_modules_server<M: 'static + TxIndexModuleSet<BaseKVQStore, ChainQuery>>() {
+  let config = Arc::new(Config::from_args());
+  if let Err(e) = start_txindex_modules_server_with_config::<M>(config) {
+      log::error!("server failed: {}", e.display_chain());
+      process::exit(1);
+  }
+}
+
+/// Like `start_txindex_modules_server`, and also indexes the mempool with `MW` so module APIs can serve pending state.
+pub fn start_txindex_modules_server_with_mempool<M: 'static + TxIndexModuleSet<BaseKVQStore, ChainQuery>, MW: TxIndexMempoolWorker<BaseKVQStore, ChainQuery>>() {
+  let config = Arc::new(Config::from_args());
+  if let Err(e) = start_txindex_modules_server_with_mempool_with_config::<M, MW>(config) {
+      log::error!("server failed: {}", e.display_chain());
+      process::exit(1);
+  }
+}
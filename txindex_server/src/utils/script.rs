@@ -2,7 +2,7 @@
 
 use super::chain::{script, Script, TxIn, TxOut};
 use script::Instruction::PushBytes;
-use txindex_common::chain::Network;
+use txindex_common::chain::{dogecoin_script_to_address, Network};
 
 pub struct InnerScripts {
     pub redeem_script: Option<Script>,
@@ -25,6 +25,9 @@ pub trait ScriptToAddr {
 #[cfg(not(feature = "liquid"))]
 impl ScriptToAddr for bitcoin::Script {
     fn to_address_str(&self, network: Network) -> Option<String> {
+        if network.is_dogecoin() {
+            return dogecoin_script_to_address(self, network);
+        }
         bitcoin::Address::from_script(self, network.into())
             .map(|s| s.to_string())
             .ok()